@@ -0,0 +1,395 @@
+//! Anthropic 原生的 Messages 接口 `/messages`
+
+use crate::config::{GenerationConfig, ReasoningEffort};
+use crate::error::RequestError;
+use crate::provider::{self, FinishReason, Provider, Reasoning, Reply};
+use crate::req::{Message, Role, ToolCall};
+use crate::tools::ToolRegistry;
+use kovi::serde_json::{self, Value, json};
+
+const API_VERSION: &str = "2023-06-01";
+/// Messages 接口必须指定最大输出 token 数，没有配置 `max_tokens` 时使用
+const MAX_TOKENS: u32 = 4096;
+
+/// 思考程度对应的思考 token 预算，至少为 1024
+fn thinking_budget(effort: ReasoningEffort) -> u32 {
+    match effort {
+        ReasoningEffort::Minimal => 1024,
+        ReasoningEffort::Low => 2048,
+        ReasoningEffort::Medium => 8192,
+        ReasoningEffort::High => 16384,
+    }
+}
+
+pub struct Anthropic;
+
+impl Provider for Anthropic {
+    fn chat_path(&self) -> &'static str {
+        "/messages"
+    }
+
+    fn authorize(&self, req: reqwest::RequestBuilder, apikey: &str) -> reqwest::RequestBuilder {
+        req.header("x-api-key", apikey)
+            .header("anthropic-version", API_VERSION)
+    }
+
+    fn chat_request(
+        &self,
+        model: &str,
+        system: &str,
+        msgs: &[Message],
+        tools: &ToolRegistry,
+        use_tools: bool,
+        params: &GenerationConfig,
+    ) -> Value {
+        let budget = params.reasoning_effort.map(thinking_budget);
+
+        // 系统提示词是顶层字段，消息中的系统消息（如知识库）合并进去
+        let mut system = system.to_string();
+        let mut messages: Vec<Value> = Vec::with_capacity(msgs.len());
+
+        for msg in msgs.iter() {
+            let (role, blocks) = match msg.role {
+                Role::System => {
+                    system.push_str("\n\n");
+                    system.push_str(&msg.content);
+                    continue;
+                }
+                // 不允许空的文本块
+                Role::User if msg.content.is_empty() => continue,
+                Role::User => ("user", vec![text_block(&msg.content)]),
+                Role::Assistant => {
+                    let mut blocks = Vec::new();
+                    // 开启思考时，工具调用前的思考块与加密的思考块需要原样带回
+                    if budget.is_some()
+                        && let Some(reasoning) = &msg.reasoning
+                    {
+                        if let Some(signature) = &reasoning.signature {
+                            blocks.push(json!({
+                                "type": "thinking",
+                                "thinking": reasoning.text,
+                                "signature": signature,
+                            }));
+                        }
+                        for data in reasoning.redacted.iter() {
+                            blocks.push(json!({ "type": "redacted_thinking", "data": data }));
+                        }
+                    }
+                    if !msg.content.is_empty() {
+                        blocks.push(text_block(&msg.content));
+                    }
+                    for call in msg.tool_calls.iter() {
+                        blocks.push(json!({
+                            "type": "tool_use",
+                            "id": call.id,
+                            "name": call.name,
+                            "input": serde_json::from_str::<Value>(&call.arguments)
+                                .ok()
+                                .filter(Value::is_object)
+                                .unwrap_or(json!({})),
+                        }));
+                    }
+                    ("assistant", blocks)
+                }
+                // 工具结果放在用户消息中
+                Role::Tool => (
+                    "user",
+                    vec![json!({
+                        "type": "tool_result",
+                        "tool_use_id": msg.tool_call_id.clone().unwrap_or_default(),
+                        "content": msg.content,
+                    })],
+                ),
+            };
+
+            // 相邻的同角色消息必须合并，例如同一轮的多个工具结果
+            match messages.last_mut() {
+                Some(last) if last["role"] == role => {
+                    last["content"].as_array_mut().unwrap().extend(blocks);
+                }
+                _ => messages.push(json!({ "role": role, "content": blocks })),
+            }
+        }
+
+        // 思考的 token 也计入 max_tokens，所以加上思考预算
+        let max_tokens = params.max_tokens.unwrap_or(MAX_TOKENS) + budget.unwrap_or_default();
+        let mut request = json!({
+            "model": model,
+            "max_tokens": max_tokens,
+            "system": system,
+            "messages": messages,
+        });
+        // 不支持 seed，开启思考时不能修改 temperature 与 top_p。
+        // temperature 的范围是 0 到 1，比其他接口小，超出时取 1
+        if let Some(budget) = budget {
+            request["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+        } else {
+            if let Some(v) = params.temperature {
+                request["temperature"] = v.min(1.0).into();
+            }
+            if let Some(v) = params.top_p {
+                request["top_p"] = v.into();
+            }
+        }
+        if !params.stop.is_empty() {
+            request["stop_sequences"] = params.stop.clone().into();
+        }
+
+        // 消息中有工具调用时必须提供工具定义，不再使用工具时通过 tool_choice 禁止调用
+        if !tools.is_empty() {
+            request["tools"] = tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name(),
+                        "description": tool.description(),
+                        "input_schema": tool.parameters(),
+                    })
+                })
+                .collect();
+            if !use_tools {
+                request["tool_choice"] = json!({ "type": "none" });
+            }
+        }
+
+        request
+    }
+
+    fn parse_reply(&self, body: Value) -> Result<Reply, RequestError> {
+        let blocks = body["content"]
+            .as_array()
+            .ok_or_else(|| RequestError::Decode("响应中没有 content".to_string()))?;
+
+        let mut text = Vec::new();
+        let mut thinking = Vec::new();
+        let mut signature = None;
+        let mut redacted = Vec::new();
+        let mut tool_calls = Vec::new();
+        for block in blocks {
+            match block["type"].as_str() {
+                Some("text") => text.extend(block["text"].as_str().filter(|v| !v.is_empty())),
+                Some("thinking") => {
+                    thinking.extend(block["thinking"].as_str());
+                    signature = block["signature"].as_str().map(str::to_string);
+                }
+                Some("redacted_thinking") => {
+                    redacted.extend(block["data"].as_str().map(str::to_string));
+                }
+                Some("tool_use") => tool_calls.push(ToolCall {
+                    id: block["id"].as_str().unwrap_or_default().to_string(),
+                    name: block["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: block["input"].to_string(),
+                }),
+                _ => {}
+            }
+        }
+
+        let finish = match body["stop_reason"].as_str() {
+            Some("tool_use") => FinishReason::ToolCalls,
+            Some("max_tokens") => FinishReason::Length,
+            Some("refusal") => FinishReason::Refusal,
+            _ => FinishReason::Stop,
+        };
+        let usage = body["usage"].is_object().then(|| {
+            provider::usage(
+                body["usage"]["input_tokens"].as_u64().unwrap_or_default() as u32,
+                body["usage"]["output_tokens"].as_u64().unwrap_or_default() as u32,
+            )
+        });
+
+        Ok(Reply {
+            content: (!text.is_empty()).then(|| text.concat()),
+            reasoning: (!thinking.is_empty() || !redacted.is_empty()).then(|| Reasoning {
+                text: thinking.join("\n\n"),
+                signature,
+                redacted,
+            }),
+            tool_calls,
+            model: body["model"].as_str().unwrap_or_default().to_string(),
+            usage,
+            finish,
+        })
+    }
+}
+
+fn text_block(text: &str) -> Value {
+    json!({ "type": "text", "text": text })
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_messages_api() {
+    use crate::config::{Config, ProviderKind};
+    use crate::mock::MockServer;
+    use crate::req::ChatClient;
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            json!({
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "model": "claude-mock",
+                "content": [
+                    { "type": "text", "text": "让我算一下" },
+                    { "type": "tool_use", "id": "toolu_1", "name": "echo", "input": { "text": "hi" } },
+                    { "type": "tool_use", "id": "toolu_2", "name": "echo", "input": { "text": "yo" } }
+                ],
+                "stop_reason": "tool_use",
+                "usage": { "input_tokens": 20, "output_tokens": 10 }
+            }),
+        ),
+        (
+            200,
+            json!({
+                "id": "msg_2",
+                "type": "message",
+                "role": "assistant",
+                "model": "claude-mock",
+                "content": [{ "type": "text", "text": "done" }],
+                "stop_reason": "end_turn",
+                "usage": { "input_tokens": 40, "output_tokens": 5 }
+            }),
+        ),
+    ])
+    .await;
+
+    let mut config = Config {
+        apikey: Some("sk-ant-test".to_string()),
+        base_url: Some(server.base_url.clone()),
+        model_name: Some("claude-mock".to_string()),
+        provider: ProviderKind::Anthropic,
+        ..Config::default()
+    };
+    config.tools.max_iterations = 1;
+    let mut tools = ToolRegistry::new();
+    tools.register(crate::req::EchoTool);
+
+    let client = ChatClient::new(&config);
+    let res = client
+        .request_with_system(
+            "system prompt",
+            vec![
+                Message::new(Role::System, "knowledge".to_string()),
+                Message::new_with_user("hi".to_string()),
+            ],
+            &tools,
+            client.deadline(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.content, "done");
+    assert_eq!(res.usage.unwrap().total_tokens, 75);
+
+    let heads = server.heads();
+    assert_eq!(heads[0].line, "POST /v1/messages");
+    assert_eq!(heads[0].header("x-api-key"), Some("sk-ant-test"));
+    assert_eq!(heads[0].header("anthropic-version"), Some(API_VERSION));
+
+    let requests = server.requests();
+    assert_eq!(requests[0]["system"], "system prompt\n\nknowledge");
+    assert_eq!(requests[0]["tools"][0]["input_schema"]["type"], "object");
+    assert!(requests[0].get("tool_choice").is_none());
+
+    // 两个工具结果合并在同一条用户消息中，最后一轮禁止调用工具
+    let msgs = requests[1]["messages"].as_array().unwrap();
+    assert_eq!(msgs.len(), 3);
+    assert_eq!(msgs[1]["content"][1]["input"]["text"], "hi");
+    assert_eq!(msgs[2]["role"], "user");
+    assert_eq!(msgs[2]["content"][0]["tool_use_id"], "toolu_1");
+    assert_eq!(msgs[2]["content"][1]["content"], "echo: yo");
+    assert_eq!(requests[1]["tool_choice"]["type"], "none");
+}
+
+#[test]
+fn test_thinking_request() {
+    use crate::provider::Reasoning;
+
+    let msgs = vec![
+        Message::new_with_user("hi".to_string()),
+        Message {
+            reasoning: Some(Reasoning {
+                text: "需要调用工具".to_string(),
+                signature: Some("sig".to_string()),
+                redacted: vec!["secret".to_string()],
+            }),
+            ..Message::new_with_tool_calls(
+                String::new(),
+                vec![ToolCall {
+                    id: "toolu_1".to_string(),
+                    name: "echo".to_string(),
+                    arguments: r#"{"text":"hi"}"#.to_string(),
+                }],
+            )
+        },
+        Message::new_with_tool_result("toolu_1".to_string(), "echo: hi".to_string()),
+    ];
+    let params = GenerationConfig {
+        temperature: Some(0.2),
+        reasoning_effort: Some(ReasoningEffort::Low),
+        ..GenerationConfig::default()
+    };
+    let request =
+        Anthropic.chat_request("m", "system", &msgs, &ToolRegistry::new(), false, &params);
+
+    assert_eq!(request["thinking"]["budget_tokens"], 2048);
+    assert_eq!(request["max_tokens"], MAX_TOKENS + 2048);
+    assert!(request.get("temperature").is_none());
+    assert_eq!(request["messages"][1]["content"][0]["type"], "thinking");
+    assert_eq!(request["messages"][1]["content"][0]["signature"], "sig");
+    assert_eq!(
+        request["messages"][1]["content"][1]["type"],
+        "redacted_thinking"
+    );
+    assert_eq!(request["messages"][1]["content"][1]["data"], "secret");
+    // 空的回答文本不生成文本块
+    assert_eq!(request["messages"][1]["content"][2]["type"], "tool_use");
+
+    // 超出 Anthropic 范围的 temperature 取 1
+    let params = GenerationConfig {
+        temperature: Some(1.5),
+        ..GenerationConfig::default()
+    };
+    let request = Anthropic.chat_request(
+        "m",
+        "system",
+        &msgs[..1],
+        &ToolRegistry::new(),
+        false,
+        &params,
+    );
+    assert_eq!(request["temperature"], 1.0);
+
+    let reply = Anthropic
+        .parse_reply(json!({
+            "model": "m",
+            "content": [
+                { "type": "thinking", "thinking": "想一想", "signature": "sig2" },
+                { "type": "text", "text": "答案" }
+            ],
+            "stop_reason": "end_turn"
+        }))
+        .unwrap();
+    assert_eq!(reply.content.as_deref(), Some("答案"));
+    let reasoning = reply.reasoning.unwrap();
+    assert_eq!(reasoning.text, "想一想");
+    assert_eq!(reasoning.signature.as_deref(), Some("sig2"));
+
+    // 只有加密的思考块时也要保存
+    let reply = Anthropic
+        .parse_reply(json!({
+            "model": "m",
+            "content": [
+                { "type": "redacted_thinking", "data": "secret2" },
+                { "type": "text", "text": "" },
+                { "type": "tool_use", "id": "toolu_2", "name": "echo", "input": {} }
+            ],
+            "stop_reason": "tool_use"
+        }))
+        .unwrap();
+    assert_eq!(reply.content, None);
+    let reasoning = reply.reasoning.unwrap();
+    assert_eq!(reasoning.text, "");
+    assert_eq!(reasoning.redacted, ["secret2"]);
+}
@@ -0,0 +1,136 @@
+//! 模型接口的熔断器
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// 正常请求
+    Closed,
+    /// 连续失败次数过多，暂时跳过
+    Open,
+    /// 冷却结束，只允许一个请求试探，成功后恢复，失败后重新熔断
+    HalfOpen,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// 连续失败次数，0 为不熔断
+    threshold: u32,
+    cooldown: Duration,
+    failures: u32,
+    opened_at: Option<Instant>,
+    /// 半开状态下正在试探的请求开始的时间，超过冷却时间视为已丢失
+    probing: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            failures: 0,
+            opened_at: None,
+            probing: None,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        match self.opened_at {
+            None => State::Closed,
+            Some(t) if t.elapsed() >= self.cooldown => State::HalfOpen,
+            Some(_) => State::Open,
+        }
+    }
+
+    pub fn allows(&self) -> bool {
+        match self.state() {
+            State::Closed => true,
+            State::Open => false,
+            State::HalfOpen => !self.probing(),
+        }
+    }
+
+    fn probing(&self) -> bool {
+        self.probing.is_some_and(|t| t.elapsed() < self.cooldown)
+    }
+
+    /// 开始请求前调用，半开状态下已有请求在试探时返回 false，
+    /// 否则由这次请求试探
+    pub fn acquire(&mut self) -> bool {
+        if self.state() != State::HalfOpen {
+            return true;
+        }
+        if self.probing() {
+            return false;
+        }
+        self.probing = Some(Instant::now());
+        true
+    }
+
+    /// 试探的请求没有结果（如请求本身有误）时释放，让下一个请求试探
+    pub fn release(&mut self) {
+        self.probing = None;
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// 熔断后剩余的冷却时间
+    pub fn remaining(&self) -> Option<Duration> {
+        let opened_at = self.opened_at?;
+        Some(self.cooldown.saturating_sub(opened_at.elapsed()))
+    }
+
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.opened_at = None;
+        self.probing = None;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failures += 1;
+        self.probing = None;
+        if self.threshold == 0 {
+            return;
+        }
+        if self.state() == State::HalfOpen || self.failures >= self.threshold {
+            self.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let mut breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+    breaker.record_failure();
+    assert_eq!(breaker.state(), State::Closed);
+    breaker.record_failure();
+    assert_eq!(breaker.state(), State::Open);
+    assert!(!breaker.allows());
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert_eq!(breaker.state(), State::HalfOpen);
+    // 只允许一个请求试探
+    assert!(breaker.acquire());
+    assert!(!breaker.allows());
+    assert!(!breaker.acquire());
+    breaker.release();
+    assert!(breaker.acquire());
+    // 试探失败后重新熔断
+    breaker.record_failure();
+    assert_eq!(breaker.state(), State::Open);
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(breaker.acquire());
+    breaker.record_success();
+    assert!(breaker.acquire());
+    assert_eq!(breaker.state(), State::Closed);
+    assert_eq!(breaker.failures(), 0);
+
+    let mut never = CircuitBreaker::new(0, Duration::from_secs(60));
+    for _ in 0..10 {
+        never.record_failure();
+    }
+    assert!(never.allows());
+}
@@ -1,8 +1,13 @@
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-use headless_chrome::Browser;
-use headless_chrome::protocol::cdp::{Emulation, Page};
-use headless_chrome::types::Bounds;
+use headless_chrome::browser::tab::RequestPausedDecision;
+use headless_chrome::protocol::cdp::Fetch::FailRequest;
+use headless_chrome::protocol::cdp::Fetch::events::RequestPausedEvent;
+use headless_chrome::protocol::cdp::{Emulation, Network, Page};
+use headless_chrome::types::{Bounds, PrintToPdfOptions};
+use headless_chrome::{Browser, Tab};
 
 use crate::error::ScreenshotError;
 
@@ -22,30 +27,7 @@ impl ScreenshotManager {
         &mut self,
         full_file_path: P,
     ) -> Result<Vec<u8>, ScreenshotError> {
-        let file_path = full_file_path.as_ref();
-
-        let tab = match self.browser.new_tab() {
-            Ok(tab) => tab,
-            Err(_) => {
-                self.restart_browser().map_err(|restart_err| {
-                    ScreenshotError::TabCreateErr(restart_err.to_string())
-                })?;
-                self.browser
-                    .new_tab()
-                    .map_err(|new_tab_err| ScreenshotError::TabCreateErr(new_tab_err.to_string()))?
-            }
-        };
-
-        tab.navigate_to(&format!(
-            "file://{}",
-            file_path
-                .to_str()
-                .ok_or(ScreenshotError::InvalidFilePath("".to_string()))?
-        ))
-        .map_err(|err| ScreenshotError::InvalidFilePath(err.to_string()))?;
-
-        tab.wait_for_element("div.finish")
-            .map_err(|err| ScreenshotError::TabOperateErr(err.to_string()))?;
+        let tab = self.open(full_file_path.as_ref())?;
 
         let viewport = tab
             .wait_for_element("article.markdown-body")
@@ -54,8 +36,6 @@ impl ScreenshotManager {
             .map_err(|err| ScreenshotError::TabOperateErr(err.to_string()))?
             .margin_viewport();
 
-        // println!("111111111: {:?}", viewport);
-
         tab.set_bounds(Bounds::Normal {
             left: Some(0),
             top: Some(0),
@@ -95,6 +75,58 @@ impl ScreenshotManager {
         Ok(png_data)
     }
 
+    /// 将页面打印为 PDF
+    pub fn print_pdf<P: AsRef<Path>>(
+        &mut self,
+        full_file_path: P,
+    ) -> Result<Vec<u8>, ScreenshotError> {
+        let tab = self.open(full_file_path.as_ref())?;
+
+        let pdf_data = tab
+            .print_to_pdf(Some(PrintToPdfOptions {
+                print_background: Some(true),
+                ..Default::default()
+            }))
+            .map_err(|err| ScreenshotError::PdfCreateErr(err.to_string()))?;
+
+        let _ = tab.close(true);
+
+        Ok(pdf_data)
+    }
+
+    /// 打开新标签页加载文件，并等待页面完成
+    fn open(&mut self, file_path: &Path) -> Result<Arc<Tab>, ScreenshotError> {
+        let tab = self.new_tab()?;
+
+        tab.navigate_to(&format!(
+            "file://{}",
+            file_path
+                .to_str()
+                .ok_or(ScreenshotError::InvalidFilePath("".to_string()))?
+        ))
+        .map_err(|err| ScreenshotError::InvalidFilePath(err.to_string()))?;
+
+        tab.wait_for_element("div.finish")
+            .map_err(|err| ScreenshotError::TabOperateErr(err.to_string()))?;
+
+        Ok(tab)
+    }
+
+    /// 打开新标签页，浏览器已退出时重新启动
+    pub fn new_tab(&mut self) -> Result<Arc<Tab>, ScreenshotError> {
+        match self.browser.new_tab() {
+            Ok(tab) => Ok(tab),
+            Err(_) => {
+                self.restart_browser().map_err(|restart_err| {
+                    ScreenshotError::TabCreateErr(restart_err.to_string())
+                })?;
+                self.browser
+                    .new_tab()
+                    .map_err(|new_tab_err| ScreenshotError::TabCreateErr(new_tab_err.to_string()))
+            }
+        }
+    }
+
     fn restart_browser(&mut self) -> Result<(), ScreenshotError> {
         let browser =
             Browser::default().map_err(|err| ScreenshotError::BrowserCreateErr(err.to_string()))?;
@@ -103,3 +135,59 @@ impl ScreenshotManager {
         Ok(())
     }
 }
+
+/// 在 `tab` 中加载网页，返回标题与正文文本，用于读取需要运行 JS 的页面。完成后关闭标签页
+///
+/// 页面发出的每个请求（包括重定向、跳转与子资源）都先交给 `allow` 检查，
+/// 参数为链接与是否为页面本身，不允许的请求直接失败。
+/// 不需要持有 [`ScreenshotManager`]，加载较慢的网页时不影响渲染图片
+pub fn page_text(
+    tab: &Tab,
+    url: &str,
+    timeout: Duration,
+    allow: impl Fn(&str, bool) -> bool + Send + Sync + 'static,
+) -> Result<(String, String), ScreenshotError> {
+    tab.set_default_timeout(timeout);
+
+    let intercept = move |_, _, event: RequestPausedEvent| {
+        let params = event.params;
+        let document = params.resource_Type == Network::ResourceType::Document;
+        if allow(&params.request.url, document) {
+            RequestPausedDecision::Continue(None)
+        } else {
+            RequestPausedDecision::Fail(FailRequest {
+                request_id: params.request_id,
+                error_reason: Network::ErrorReason::BlockedByClient,
+            })
+        }
+    };
+    let res = tab
+        .enable_fetch(None, None)
+        .and_then(|tab| tab.enable_request_interception(Arc::new(intercept)))
+        .map_err(|err| ScreenshotError::TabOperateErr(err.to_string()));
+    if let Err(err) = res {
+        let _ = tab.close(true);
+        return Err(err);
+    }
+
+    let res = tab
+        .navigate_to(url)
+        .and_then(|tab| tab.wait_until_navigated())
+        .and_then(|tab| {
+            let title = tab.get_title()?;
+            let text = tab.evaluate("document.body ? document.body.innerText : ''", false)?;
+            Ok((
+                title,
+                text.value
+                    .as_ref()
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            ))
+        })
+        .map_err(|err| ScreenshotError::TabOperateErr(err.to_string()));
+
+    let _ = tab.close(true);
+
+    res
+}
@@ -0,0 +1,257 @@
+//! 回答缓存：相同（或 embedding 足够相似）的问题直接使用之前的回答，
+//! 图片模式下主题相同时连同图片一起复用
+
+use crate::config::CacheConfig;
+use crate::req::Completion;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    scope: String,
+    key: String,
+    embedding: Option<Vec<f32>>,
+    content: String,
+    model: String,
+    created: Instant,
+    /// 最近一次命中或写入的时间
+    used: Instant,
+    /// 渲染好的图片与渲染时是否为浅色主题
+    png: Option<(bool, Vec<u8>)>,
+}
+
+#[derive(Default)]
+pub struct AnswerCache {
+    entries: Vec<Entry>,
+    /// 所有图片的总字节数
+    png_bytes: usize,
+}
+
+/// 命中的缓存
+pub struct Hit {
+    pub key: String,
+    pub completion: Completion,
+    pub png: Option<(bool, Vec<u8>)>,
+}
+
+/// 问题的归一化形式：去掉空白与标点，英文转为小写
+pub fn key(question: &str, quote: Option<&str>) -> String {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(|c| c.to_lowercase())
+            .collect()
+    };
+    match quote {
+        Some(quote) => format!("{}\n{}", normalize(quote), normalize(question)),
+        None => normalize(question),
+    }
+}
+
+impl AnswerCache {
+    fn expire(&mut self, ttl: Duration) {
+        self.entries.retain(|entry| entry.created.elapsed() < ttl);
+        self.count_png_bytes();
+    }
+
+    fn count_png_bytes(&mut self) {
+        self.png_bytes = self
+            .entries
+            .iter()
+            .filter_map(|entry| entry.png.as_ref())
+            .map(|(_, png)| png.len())
+            .sum();
+    }
+
+    pub fn get(
+        &mut self,
+        scope: &str,
+        key: &str,
+        embedding: Option<&[f32]>,
+        config: &CacheConfig,
+    ) -> Option<Hit> {
+        self.expire(Duration::from_secs(config.ttl_secs));
+
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.scope == scope && entry.key == key)
+            .or_else(|| {
+                let embedding = embedding?;
+                self.entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| entry.scope == scope)
+                    .filter_map(|(i, entry)| {
+                        let similarity =
+                            crate::knowledge::cosine(embedding, entry.embedding.as_deref()?);
+                        (similarity >= config.similarity).then_some((similarity, i))
+                    })
+                    .max_by(|a, b| a.0.total_cmp(&b.0))
+                    .map(|(_, i)| i)
+            })?;
+        let entry = &mut self.entries[index];
+        entry.used = Instant::now();
+
+        Some(Hit {
+            key: entry.key.clone(),
+            completion: Completion {
+                content: entry.content.clone(),
+                model: entry.model.clone(),
+                usage: None,
+                latency: Duration::ZERO,
+                used_tools: false,
+                provider: "cache".to_string(),
+                reasoning: None,
+            },
+            png: entry.png.clone(),
+        })
+    }
+
+    pub fn insert(
+        &mut self,
+        scope: &str,
+        key: String,
+        embedding: Option<Vec<f32>>,
+        res: &Completion,
+        config: &CacheConfig,
+    ) {
+        self.entries
+            .retain(|entry| !(entry.scope == scope && entry.key == key));
+        self.entries.push(Entry {
+            scope: scope.to_string(),
+            key,
+            embedding,
+            content: res.content.clone(),
+            model: res.model.clone(),
+            created: Instant::now(),
+            used: Instant::now(),
+            png: None,
+        });
+        let over = self.entries.len().saturating_sub(config.max_entries);
+        self.entries.drain(..over);
+        self.count_png_bytes();
+    }
+
+    /// 保存回答渲染好的图片。图片总大小超过 `max_bytes` 时，丢弃最久没有使用的回答的图片，
+    /// 回答本身仍然保留，命中时重新渲染
+    pub fn set_png(
+        &mut self,
+        scope: &str,
+        key: &str,
+        light: bool,
+        png: Vec<u8>,
+        config: &CacheConfig,
+    ) {
+        if png.len() > config.max_bytes {
+            return;
+        }
+        let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.scope == scope && entry.key == key)
+        else {
+            return;
+        };
+        entry.png = Some((light, png));
+        entry.used = Instant::now();
+        self.count_png_bytes();
+
+        while self.png_bytes > config.max_bytes {
+            let Some(lru) = self
+                .entries
+                .iter_mut()
+                .filter(|entry| entry.png.is_some())
+                .min_by_key(|entry| entry.used)
+            else {
+                break;
+            };
+            if let Some((_, png)) = lru.png.take() {
+                self.png_bytes -= png.len();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cache() {
+    use crate::mock;
+
+    let config = CacheConfig {
+        max_entries: 2,
+        ..CacheConfig::default()
+    };
+    let mut cache = AnswerCache::default();
+
+    assert_eq!(key("What is Rust?", None), key("what is rust", None));
+
+    cache.insert(
+        "g1",
+        key("什么是 Rust？", None),
+        Some(vec![1.0, 0.0]),
+        &mock::completion("a"),
+        &config,
+    );
+    let hit = cache
+        .get("g1", &key("什么是rust", None), None, &config)
+        .unwrap();
+    assert_eq!(hit.completion.content, "a");
+
+    // 不同的群互相隔离
+    assert!(
+        cache
+            .get("g2", &key("什么是rust", None), None, &config)
+            .is_none()
+    );
+
+    // embedding 相似度
+    let similar = cache.get("g1", "other", Some(&[0.99, 0.05]), &config);
+    assert_eq!(similar.unwrap().key, key("什么是rust", None));
+    assert!(
+        cache
+            .get("g1", "other", Some(&[0.0, 1.0]), &config)
+            .is_none()
+    );
+
+    // 超出数量时淘汰最早的
+    cache.insert("g1", "b".to_string(), None, &mock::completion("b"), &config);
+    cache.insert("g1", "c".to_string(), None, &mock::completion("c"), &config);
+    assert!(
+        cache
+            .get("g1", &key("什么是rust", None), None, &config)
+            .is_none()
+    );
+
+    cache.set_png("g1", "c", true, vec![1, 2, 3], &config);
+    assert_eq!(
+        cache.get("g1", "c", None, &config).unwrap().png,
+        Some((true, vec![1, 2, 3]))
+    );
+}
+
+#[test]
+fn test_cache_png_bytes() {
+    use crate::mock;
+
+    let config = CacheConfig {
+        max_bytes: 5,
+        ..CacheConfig::default()
+    };
+    let res = mock::completion("a");
+    let mut cache = AnswerCache::default();
+    for key in ["a", "b", "c"] {
+        cache.insert("g", key.to_string(), None, &res, &config);
+    }
+
+    cache.set_png("g", "a", true, vec![0; 2], &config);
+    cache.set_png("g", "b", true, vec![0; 2], &config);
+    // 命中后 a 比 b 更近使用，超出时丢弃 b 的图片
+    assert!(cache.get("g", "a", None, &config).unwrap().png.is_some());
+    cache.set_png("g", "c", true, vec![0; 2], &config);
+    assert!(cache.get("g", "b", None, &config).unwrap().png.is_none());
+    assert!(cache.get("g", "a", None, &config).unwrap().png.is_some());
+    assert!(cache.get("g", "c", None, &config).unwrap().png.is_some());
+
+    // 单张超过上限的图片不缓存，回答仍然保留
+    cache.set_png("g", "b", true, vec![0; 6], &config);
+    assert!(cache.get("g", "b", None, &config).unwrap().png.is_none());
+    assert_eq!(cache.png_bytes, 4);
+}
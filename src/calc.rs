@@ -0,0 +1,1684 @@
+//! 计算器工具：精确的分数运算、单位换算与日期计算，完全在本地执行
+//!
+//! 四则运算、乘方与阶乘使用任意精度的分数精确计算，开方、三角函数等使用 f64 近似计算。
+
+use crate::tools::Tool;
+use kovi::chrono::{self, Datelike as _, NaiveDate, NaiveDateTime, TimeDelta, Timelike as _};
+use kovi::futures_util::future::BoxFuture;
+use kovi::serde_json::{Value, json};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// 数字最多的位数（以 10^9 为一位），防止乘方和阶乘耗尽资源
+const MAX_LIMBS: usize = 1200;
+/// 非整数结果显示的小数位数
+const DECIMAL_DIGITS: usize = 30;
+/// 整数结果最多显示的位数
+const MAX_DISPLAY_DIGITS: usize = 1000;
+/// 分子或分母超过这个位数时不再显示精确的分数
+const MAX_FRACTION_DIGITS: usize = 40;
+/// 小数点后连续的 0 超过这个数时改用科学计数法
+const MAX_LEADING_ZEROS: usize = 9;
+
+const BASE: u64 = 1_000_000_000;
+
+/// 无符号大整数，以 10^9 为基数小端存储，高位没有多余的 0
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+
+impl BigUint {
+    fn zero() -> BigUint {
+        BigUint(Vec::new())
+    }
+
+    fn from_u64(mut v: u64) -> BigUint {
+        let mut limbs = Vec::new();
+        while v > 0 {
+            limbs.push((v % BASE) as u32);
+            v /= BASE;
+        }
+        BigUint(limbs)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn is_one(&self) -> bool {
+        self.0 == [1]
+    }
+
+    /// 十进制位数，0 为 0 位
+    fn digits(&self) -> usize {
+        match self.0.last() {
+            None => 0,
+            Some(last) => (self.0.len() - 1) * 9 + last.to_string().len(),
+        }
+    }
+
+    fn normalize(mut self) -> BigUint {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+
+    fn add(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.0.len().max(other.0.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.0.len().max(other.0.len()) {
+            let v =
+                *self.0.get(i).unwrap_or(&0) as u64 + *other.0.get(i).unwrap_or(&0) as u64 + carry;
+            limbs.push((v % BASE) as u32);
+            carry = v / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        BigUint(limbs)
+    }
+
+    /// 要求 `self >= other`
+    fn sub(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.0.len());
+        let mut borrow = 0;
+        for i in 0..self.0.len() {
+            let mut v = self.0[i] as i64 - *other.0.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = 0;
+            if v < 0 {
+                v += BASE as i64;
+                borrow = 1;
+            }
+            limbs.push(v as u32);
+        }
+        BigUint(limbs).normalize()
+    }
+
+    fn mul(&self, other: &BigUint) -> BigUint {
+        if self.is_zero() || other.is_zero() {
+            return BigUint::zero();
+        }
+        let mut limbs = vec![0u64; self.0.len() + other.0.len()];
+        for (i, a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, b) in other.0.iter().enumerate() {
+                let v = limbs[i + j] + *a as u64 * *b as u64 + carry;
+                limbs[i + j] = v % BASE;
+                carry = v / BASE;
+            }
+            let mut k = i + other.0.len();
+            while carry > 0 {
+                let v = limbs[k] + carry;
+                limbs[k] = v % BASE;
+                carry = v / BASE;
+                k += 1;
+            }
+        }
+        BigUint(limbs.into_iter().map(|v| v as u32).collect()).normalize()
+    }
+
+    fn mul_small(&self, m: u32) -> BigUint {
+        self.mul(&BigUint::from_u64(m as u64))
+    }
+
+    /// 要求 `divisor` 不为 0
+    fn divmod(&self, divisor: &BigUint) -> (BigUint, BigUint) {
+        if self.cmp(divisor) == Ordering::Less {
+            return (BigUint::zero(), self.clone());
+        }
+        if let [d] = divisor.0[..] {
+            let mut quotient = vec![0u32; self.0.len()];
+            let mut rem = 0u64;
+            for i in (0..self.0.len()).rev() {
+                let v = rem * BASE + self.0[i] as u64;
+                quotient[i] = (v / d as u64) as u32;
+                rem = v % d as u64;
+            }
+            return (BigUint(quotient).normalize(), BigUint::from_u64(rem));
+        }
+
+        let mut quotient = vec![0u32; self.0.len()];
+        let mut rem = BigUint::zero();
+        for i in (0..self.0.len()).rev() {
+            rem.0.insert(0, self.0[i]);
+            rem = rem.normalize();
+
+            // 用最高几位估计这一位的商，再修正误差
+            let n = divisor.0.len();
+            let top = |v: &BigUint| {
+                v.0.iter()
+                    .skip(n - 2)
+                    .rev()
+                    .fold(0u128, |acc, v| acc * BASE as u128 + *v as u128)
+            };
+            let mut lo = (top(&rem) / top(divisor)).min(BASE as u128 - 1) as u32;
+            let mut product = divisor.mul_small(lo);
+            while product > rem {
+                lo -= 1;
+                product = product.sub(divisor);
+            }
+            rem = rem.sub(&product);
+            while rem >= *divisor {
+                lo += 1;
+                rem = rem.sub(divisor);
+            }
+            quotient[i] = lo;
+        }
+
+        (BigUint(quotient).normalize(), rem)
+    }
+
+    fn gcd(&self, other: &BigUint) -> BigUint {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        while !b.is_zero() {
+            let (_, r) = a.divmod(&b);
+            a = b;
+            b = r;
+        }
+        a
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.0
+            .iter()
+            .rev()
+            .fold(0.0, |acc, v| acc * BASE as f64 + *v as f64)
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        match self.0.len() {
+            0 => Some(0),
+            1 => Some(self.0[0] as u64),
+            2 => Some(self.0[1] as u64 * BASE + self.0[0] as u64),
+            _ => None,
+        }
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.split_last() {
+            None => write!(f, "0"),
+            Some((last, rest)) => {
+                write!(f, "{}", last)?;
+                for v in rest.iter().rev() {
+                    write!(f, "{:09}", v)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// 精确的分数，分母为正且已约分
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rational {
+    neg: bool,
+    num: BigUint,
+    den: BigUint,
+}
+
+impl Rational {
+    fn new(neg: bool, num: BigUint, den: BigUint) -> Rational {
+        if den.is_one() {
+            return Rational {
+                neg: neg && !num.is_zero(),
+                num,
+                den,
+            };
+        }
+        let gcd = num.gcd(&den);
+        let (num, den) = if gcd.is_one() || gcd.is_zero() {
+            (num, den)
+        } else {
+            (num.divmod(&gcd).0, den.divmod(&gcd).0)
+        };
+        Rational {
+            neg: neg && !num.is_zero(),
+            num,
+            den,
+        }
+    }
+
+    fn from_i64(v: i64) -> Rational {
+        Rational::new(
+            v < 0,
+            BigUint::from_u64(v.unsigned_abs()),
+            BigUint::from_u64(1),
+        )
+    }
+
+    /// 解析十进制数，如 `12.5`、`1e-3`
+    fn parse(s: &str) -> Option<Rational> {
+        let (mantissa, exp) = match s.find(['e', 'E']) {
+            Some(i) => (&s[..i], s[i + 1..].parse::<i32>().ok()?),
+            None => (s, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if int.is_empty() && frac.is_empty() {
+            return None;
+        }
+
+        let mut num = BigUint::zero();
+        for c in int.chars().chain(frac.chars()) {
+            num = num
+                .mul_small(10)
+                .add(&BigUint::from_u64(c.to_digit(10)? as u64));
+        }
+        let exp = exp - frac.len() as i32;
+        if exp.unsigned_abs() as usize > MAX_LIMBS * 9 {
+            return None;
+        }
+        let scale = pow10(exp.unsigned_abs());
+        Some(if exp >= 0 {
+            Rational::new(false, num.mul(&scale), BigUint::from_u64(1))
+        } else {
+            Rational::new(false, num, scale)
+        })
+    }
+
+    fn is_zero(&self) -> bool {
+        self.num.is_zero()
+    }
+
+    fn is_integer(&self) -> bool {
+        self.den.is_one()
+    }
+
+    fn neg(&self) -> Rational {
+        Rational {
+            neg: !self.neg && !self.num.is_zero(),
+            ..self.clone()
+        }
+    }
+
+    fn add(&self, other: &Rational) -> Rational {
+        let a = self.num.mul(&other.den);
+        let b = other.num.mul(&self.den);
+        let den = self.den.mul(&other.den);
+        if self.neg == other.neg {
+            Rational::new(self.neg, a.add(&b), den)
+        } else if a >= b {
+            Rational::new(self.neg, a.sub(&b), den)
+        } else {
+            Rational::new(other.neg, b.sub(&a), den)
+        }
+    }
+
+    fn sub(&self, other: &Rational) -> Rational {
+        self.add(&other.neg())
+    }
+
+    fn mul(&self, other: &Rational) -> Rational {
+        Rational::new(
+            self.neg != other.neg,
+            self.num.mul(&other.num),
+            self.den.mul(&other.den),
+        )
+    }
+
+    fn div(&self, other: &Rational) -> Result<Rational, String> {
+        if other.is_zero() {
+            return Err("除数为 0".to_string());
+        }
+        Ok(Rational::new(
+            self.neg != other.neg,
+            self.num.mul(&other.den),
+            self.den.mul(&other.num),
+        ))
+    }
+
+    /// 向下取整
+    fn floor(&self) -> Rational {
+        let (q, r) = self.num.divmod(&self.den);
+        let q = if self.neg && !r.is_zero() {
+            q.add(&BigUint::from_u64(1))
+        } else {
+            q
+        };
+        Rational::new(self.neg, q, BigUint::from_u64(1))
+    }
+
+    fn pow(&self, exp: i64) -> Result<Rational, String> {
+        if exp < 0 {
+            return Rational::from_i64(1).div(&self.pow(-exp)?);
+        }
+        let (mut base, mut exp) = (self.clone(), exp as u64);
+        let mut res = Rational::from_i64(1);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                res = res.mul(&base);
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.mul(&base);
+            }
+            check_size(&res)?;
+            check_size(&base)?;
+        }
+        Ok(res)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        if !self.is_integer() {
+            return None;
+        }
+        let v = i64::try_from(self.num.to_u64()?).ok()?;
+        Some(if self.neg { -v } else { v })
+    }
+
+    fn to_f64(&self) -> f64 {
+        // 过大的分子分母先按位数缩小，避免 inf/inf
+        let shift = self.num.0.len().min(self.den.0.len()).saturating_sub(30);
+        let num = BigUint(self.num.0[shift..].to_vec()).to_f64();
+        let den = BigUint(self.den.0[shift..].to_vec()).to_f64();
+        let v = num / den;
+        if self.neg { -v } else { v }
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.neg { "-" } else { "" };
+        let (int, mut rem) = self.num.divmod(&self.den);
+
+        if rem.is_zero() {
+            let digits = int.to_string();
+            if digits.len() > MAX_DISPLAY_DIGITS {
+                return write!(
+                    f,
+                    "{}{}.{}…×10^{}（共 {} 位）",
+                    sign,
+                    &digits[..1],
+                    &digits[1..30],
+                    digits.len() - 1,
+                    digits.len()
+                );
+            }
+            return write!(f, "{}{}", sign, digits);
+        }
+
+        let mut frac = String::new();
+        while !rem.is_zero() && frac.len() < DECIMAL_DIGITS {
+            let (digit, r) = rem.mul_small(10).divmod(&self.den);
+            frac.push_str(&digit.to_string());
+            rem = r;
+        }
+
+        // 整数部分过长或绝对值过小时，小数形式看不出有效数字，改用科学计数法
+        let leading_zeros = frac.len() - frac.trim_start_matches('0').len();
+        let approx = if int.digits() > MAX_DISPLAY_DIGITS
+            || (int.is_zero() && leading_zeros > MAX_LEADING_ZEROS)
+        {
+            format!("{}{}", sign, self.scientific())
+        } else if rem.is_zero() {
+            return write!(f, "{}{}.{}", sign, int, frac);
+        } else {
+            format!("{}{}.{}…", sign, int, frac)
+        };
+
+        if self.num.digits() > MAX_FRACTION_DIGITS || self.den.digits() > MAX_FRACTION_DIGITS {
+            write!(f, "≈ {}", approx)
+        } else {
+            write!(f, "{}{}/{} ≈ {}", sign, self.num, self.den, approx)
+        }
+    }
+}
+
+impl Rational {
+    /// 绝对值的科学计数法形式，保留 `DECIMAL_DIGITS` 位有效数字，要求不为 0
+    fn scientific(&self) -> String {
+        // 值在 10^(e-1) 到 10^(e+1) 之间，放大到至少 DECIMAL_DIGITS 位整数后再确定指数
+        let e = self.num.digits() as i64 - self.den.digits() as i64;
+        let shift = DECIMAL_DIGITS as i64 - e;
+        let (mantissa, _) = if shift >= 0 {
+            self.num.mul(&pow10(shift as u32)).divmod(&self.den)
+        } else {
+            self.num.divmod(&self.den.mul(&pow10(-shift as u32)))
+        };
+        let digits = mantissa.to_string();
+        let exp = digits.len() as i64 - 1 - shift;
+        let rest = digits[1..DECIMAL_DIGITS].trim_end_matches('0');
+        if rest.is_empty() {
+            format!("{}…×10^{}", &digits[..1], exp)
+        } else {
+            format!("{}.{}…×10^{}", &digits[..1], rest, exp)
+        }
+    }
+}
+
+fn pow10(exp: u32) -> BigUint {
+    let mut v = BigUint::from_u64(1);
+    for _ in 0..exp / 9 {
+        v.0.insert(0, 0);
+    }
+    v.mul_small(10u32.pow(exp % 9))
+}
+
+fn check_size(v: &Rational) -> Result<(), String> {
+    if v.num.0.len() > MAX_LIMBS || v.den.0.len() > MAX_LIMBS {
+        return Err("结果过大".to_string());
+    }
+    Ok(())
+}
+
+/// 数值，能精确计算时使用分数，否则使用 f64
+#[derive(Clone, Debug)]
+enum Num {
+    Exact(Rational),
+    Approx(f64),
+}
+
+impl Num {
+    fn from_i64(v: i64) -> Num {
+        Num::Exact(Rational::from_i64(v))
+    }
+
+    /// 解析十进制数或 `a/b` 形式的分数
+    fn parse(s: &str) -> Option<Num> {
+        match s.split_once('/') {
+            Some((a, b)) => Rational::parse(a)?
+                .div(&Rational::parse(b)?)
+                .ok()
+                .map(Num::Exact),
+            None => Rational::parse(s).map(Num::Exact),
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        match self {
+            Num::Exact(v) => v.to_f64(),
+            Num::Approx(v) => *v,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Num::Exact(v) => v.is_zero(),
+            Num::Approx(v) => *v == 0.0,
+        }
+    }
+
+    fn approx(v: f64) -> Result<Num, String> {
+        if v.is_finite() {
+            Ok(Num::Approx(v))
+        } else {
+            Err("结果不是有限的数".to_string())
+        }
+    }
+
+    fn neg(&self) -> Num {
+        match self {
+            Num::Exact(v) => Num::Exact(v.neg()),
+            Num::Approx(v) => Num::Approx(-v),
+        }
+    }
+
+    fn add(&self, other: &Num) -> Result<Num, String> {
+        match (self, other) {
+            (Num::Exact(a), Num::Exact(b)) => Ok(Num::Exact(a.add(b))),
+            _ => Num::approx(self.to_f64() + other.to_f64()),
+        }
+    }
+
+    fn sub(&self, other: &Num) -> Result<Num, String> {
+        self.add(&other.neg())
+    }
+
+    fn mul(&self, other: &Num) -> Result<Num, String> {
+        match (self, other) {
+            (Num::Exact(a), Num::Exact(b)) => {
+                let v = a.mul(b);
+                check_size(&v)?;
+                Ok(Num::Exact(v))
+            }
+            _ => Num::approx(self.to_f64() * other.to_f64()),
+        }
+    }
+
+    fn div(&self, other: &Num) -> Result<Num, String> {
+        if other.is_zero() {
+            return Err("除数为 0".to_string());
+        }
+        match (self, other) {
+            (Num::Exact(a), Num::Exact(b)) => Ok(Num::Exact(a.div(b)?)),
+            _ => Num::approx(self.to_f64() / other.to_f64()),
+        }
+    }
+
+    /// 取余，结果与除数同号
+    fn rem(&self, other: &Num) -> Result<Num, String> {
+        if other.is_zero() {
+            return Err("除数为 0".to_string());
+        }
+        match (self, other) {
+            (Num::Exact(a), Num::Exact(b)) => Ok(Num::Exact(a.sub(&b.mul(&a.div(b)?.floor())))),
+            _ => {
+                let (a, b) = (self.to_f64(), other.to_f64());
+                Num::approx(a - b * (a / b).floor())
+            }
+        }
+    }
+
+    fn pow(&self, exp: &Num) -> Result<Num, String> {
+        if let (Num::Exact(base), Num::Exact(e)) = (self, exp)
+            && let Some(e) = e.to_i64()
+        {
+            if e.unsigned_abs() > 100_000 {
+                return Err("指数过大".to_string());
+            }
+            if base.is_zero() && e < 0 {
+                return Err("除数为 0".to_string());
+            }
+            return Ok(Num::Exact(base.pow(e)?));
+        }
+        Num::approx(self.to_f64().powf(exp.to_f64()))
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        match self {
+            Num::Exact(v) => v.to_i64(),
+            Num::Approx(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Num::Exact(v) => write!(f, "{}", v),
+            Num::Approx(v) => {
+                if v.abs() >= 1e15 || (v.abs() < 1e-6 && *v != 0.0) {
+                    write!(f, "{:e}", v)
+                } else {
+                    // 保留 15 位有效数字，去掉末尾的 0
+                    let digits = (14 - v.abs().log10().floor().max(0.0) as i32).max(0) as usize;
+                    let s = format!("{:.*}", digits, v);
+                    let s = if s.contains('.') {
+                        s.trim_end_matches('0').trim_end_matches('.')
+                    } else {
+                        &s
+                    };
+                    write!(f, "{}", s)
+                }
+            }
+        }
+    }
+}
+
+/// 量纲：长度、质量、时间、信息量、温度的指数
+type Dim = [i8; 5];
+
+const DIMENSIONLESS: Dim = [0; 5];
+const BASE_UNITS: [&str; 5] = ["m", "kg", "s", "bit", "K"];
+const TIME: Dim = [0, 0, 1, 0, 0];
+const DATA: Dim = [0, 0, 0, 1, 0];
+
+/// 带单位的数值，数值以国际单位制的基本单位（信息量为 bit）存储
+#[derive(Clone, Debug)]
+struct Quantity {
+    num: Num,
+    dim: Dim,
+}
+
+impl Quantity {
+    fn number(num: Num) -> Quantity {
+        Quantity {
+            num,
+            dim: DIMENSIONLESS,
+        }
+    }
+
+    fn is_dimensionless(&self) -> bool {
+        self.dim == DIMENSIONLESS
+    }
+}
+
+/// 单位：名称、换算到基本单位的系数、量纲、温度的零点偏移
+struct Unit {
+    names: &'static [&'static str],
+    /// 十进制数或 `a/b` 形式的分数
+    factor: &'static str,
+    dim: Dim,
+    /// 温度换算为开尔文时先加上的偏移，`K = (x + offset) * factor`
+    offset: Option<&'static str>,
+}
+
+macro_rules! unit {
+    ($names:expr, $factor:expr, $dim:expr) => {
+        Unit {
+            names: $names,
+            factor: $factor,
+            dim: $dim,
+            offset: None,
+        }
+    };
+}
+
+const L: Dim = [1, 0, 0, 0, 0];
+const M: Dim = [0, 1, 0, 0, 0];
+const AREA: Dim = [2, 0, 0, 0, 0];
+const VOLUME: Dim = [3, 0, 0, 0, 0];
+const TEMP: Dim = [0, 0, 0, 0, 1];
+
+static UNITS: &[Unit] = &[
+    unit!(&["m", "meter", "meters", "米"], "1", L),
+    unit!(
+        &["km", "kilometer", "kilometers", "公里", "千米"],
+        "1000",
+        L
+    ),
+    unit!(&["cm", "厘米"], "0.01", L),
+    unit!(&["mm", "毫米"], "0.001", L),
+    unit!(&["um", "μm", "微米"], "1e-6", L),
+    unit!(&["nm", "纳米"], "1e-9", L),
+    unit!(&["mi", "mile", "miles", "英里"], "1609.344", L),
+    unit!(&["ft", "foot", "feet", "英尺"], "0.3048", L),
+    unit!(&["inch", "inches", "英寸"], "0.0254", L),
+    unit!(&["yd", "yard", "yards", "码"], "0.9144", L),
+    unit!(&["nmi", "海里"], "1852", L),
+    unit!(&["g", "gram", "grams", "克"], "0.001", M),
+    unit!(&["kg", "kilogram", "kilograms", "千克", "公斤"], "1", M),
+    unit!(&["mg", "毫克"], "1e-6", M),
+    unit!(&["t", "tonne", "tonnes", "吨"], "1000", M),
+    unit!(&["lb", "lbs", "pound", "pounds", "磅"], "0.45359237", M),
+    unit!(&["oz", "ounce", "ounces", "盎司"], "0.028349523125", M),
+    unit!(&["斤"], "0.5", M),
+    unit!(&["两"], "0.05", M),
+    unit!(&["s", "sec", "second", "seconds", "秒"], "1", TIME),
+    unit!(&["ms", "毫秒"], "0.001", TIME),
+    unit!(&["us", "μs", "微秒"], "1e-6", TIME),
+    unit!(&["min", "minute", "minutes", "分钟"], "60", TIME),
+    unit!(&["h", "hr", "hour", "hours", "小时"], "3600", TIME),
+    unit!(&["d", "day", "days", "天"], "86400", TIME),
+    unit!(&["week", "weeks", "周", "星期"], "604800", TIME),
+    unit!(&["year", "years", "年"], "31556952", TIME),
+    unit!(&["bit", "bits"], "1", DATA),
+    unit!(&["B", "byte", "bytes", "字节"], "8", DATA),
+    unit!(&["KB", "kB"], "8000", DATA),
+    unit!(&["MB"], "8e6", DATA),
+    unit!(&["GB"], "8e9", DATA),
+    unit!(&["TB"], "8e12", DATA),
+    unit!(&["KiB"], "8192", DATA),
+    unit!(&["MiB"], "8388608", DATA),
+    unit!(&["GiB"], "8589934592", DATA),
+    unit!(&["TiB"], "8796093022208", DATA),
+    unit!(&["ha", "公顷"], "10000", AREA),
+    unit!(&["acre", "acres", "英亩"], "4046.8564224", AREA),
+    unit!(&["亩"], "2000/3", AREA),
+    unit!(&["L", "l", "liter", "liters", "升"], "0.001", VOLUME),
+    unit!(&["mL", "ml", "毫升"], "1e-6", VOLUME),
+    unit!(
+        &["gal", "gallon", "gallons", "加仑"],
+        "0.003785411784",
+        VOLUME
+    ),
+    Unit {
+        names: &["K", "kelvin", "开尔文"],
+        factor: "1",
+        dim: TEMP,
+        offset: Some("0"),
+    },
+    Unit {
+        names: &["C", "°C", "℃", "摄氏度"],
+        factor: "1",
+        dim: TEMP,
+        offset: Some("273.15"),
+    },
+    Unit {
+        names: &["F", "°F", "℉", "华氏度"],
+        factor: "5/9",
+        dim: TEMP,
+        offset: Some("459.67"),
+    },
+];
+
+fn unit_factor(unit: &Unit) -> Num {
+    Num::parse(unit.factor).unwrap()
+}
+
+fn find_unit(name: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|unit| unit.names.contains(&name))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(String),
+    Ident(String),
+    Date(NaiveDateTime),
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+    /// 单位换算 `to` / `in` / `转`
+    To,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if let Some((date, len)) = parse_date(&chars[i..]) {
+            tokens.push(Token::Date(date));
+            i += len;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit() || chars[i] == '.' || chars[i] == '_')
+            {
+                i += 1;
+            }
+            // 科学计数法，注意不要把 `e` 常量或单位吃掉
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let num: String = chars[start..i].iter().filter(|c| **c != '_').collect();
+            tokens.push(Token::Num(num));
+        } else if c.is_alphabetic() || c == '°' || c == '_' {
+            let start = i;
+            i += 1;
+            // 中文与字母不连在一起，如 `3km转m`
+            let cjk = |c: char| !c.is_ascii() && c != 'μ' && c != '°';
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_')
+                && cjk(chars[i]) == cjk(chars[start])
+            {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            match ident.as_str() {
+                "to" | "in" | "转" | "换算成" => tokens.push(Token::To),
+                _ => tokens.push(Token::Ident(ident)),
+            }
+        } else {
+            tokens.push(match c {
+                '+' | '-' | '*' | '/' | '%' | '^' | '!' => Token::Op(c),
+                '×' => Token::Op('*'),
+                '÷' => Token::Op('/'),
+                '−' => Token::Op('-'),
+                '(' | '（' => Token::LParen,
+                ')' | '）' => Token::RParen,
+                ',' | '，' => Token::Comma,
+                '℃' | '℉' => Token::Ident(c.to_string()),
+                _ => return Err(format!("无法识别的字符 `{}`", c)),
+            });
+            i += 1;
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// 识别 `2024-01-02`、`2024-01-02 03:04` 与 `2024-01-02T03:04:05` 形式的日期
+fn parse_date(chars: &[char]) -> Option<(NaiveDateTime, usize)> {
+    let digits = |s: &[char]| s.iter().all(|c| c.is_ascii_digit());
+    let text = |s: &[char]| s.iter().collect::<String>();
+
+    if chars.len() < 10
+        || !digits(&chars[0..4])
+        || chars[4] != '-'
+        || !digits(&chars[5..7])
+        || chars[7] != '-'
+        || !digits(&chars[8..10])
+    {
+        return None;
+    }
+    let date = NaiveDate::parse_from_str(&text(&chars[..10]), "%Y-%m-%d").ok()?;
+
+    let rest = &chars[10..];
+    if rest.len() >= 6
+        && (rest[0] == ' ' || rest[0] == 'T')
+        && digits(&rest[1..3])
+        && rest[3] == ':'
+        && digits(&rest[4..6])
+    {
+        if rest.len() >= 9 && rest[6] == ':' && digits(&rest[7..9]) {
+            let time = chrono::NaiveTime::parse_from_str(&text(&rest[1..9]), "%H:%M:%S").ok()?;
+            return Some((date.and_time(time), 19));
+        }
+        let time = chrono::NaiveTime::parse_from_str(&text(&rest[1..6]), "%H:%M").ok()?;
+        return Some((date.and_time(time), 16));
+    }
+
+    Some((date.and_hms_opt(0, 0, 0)?, 10))
+}
+
+#[derive(Clone, Debug)]
+enum CalcValue {
+    Quantity(Quantity),
+    /// 带零点偏移的温度读数，如 `30 C`，只能换算，不能参与运算
+    Reading(Quantity),
+    Date(NaiveDateTime),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    now: NaiveDateTime,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.next() {
+            Some(v) if v == token => Ok(()),
+            Some(v) => Err(format!("此处应为 {:?}，而不是 {:?}", token, v)),
+            None => Err(format!("表达式不完整，缺少 {:?}", token)),
+        }
+    }
+
+    fn sum(&mut self) -> Result<CalcValue, String> {
+        let mut left = self.product()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.pos += 1;
+            let right = self.product()?;
+            left = add(left, right, op == '-')?;
+        }
+        Ok(left)
+    }
+
+    fn product(&mut self) -> Result<CalcValue, String> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(op @ ('*' | '/' | '%'))) => {
+                    let op = *op;
+                    self.pos += 1;
+                    op
+                }
+                // 隐式乘法，如 `3 km`、`2(1 + 2)`
+                Some(Token::Ident(_) | Token::LParen) => '*',
+                // 复合单位的读数，如 `3斤2两`、`1h 30min`
+                Some(Token::Num(_)) if matches!(&left, CalcValue::Quantity(v) if !v.is_dimensionless()) =>
+                {
+                    let right = self.product()?;
+                    return add(left, right, false);
+                }
+                Some(Token::Num(v)) => return Err(format!("`{}` 前缺少运算符", v)),
+                _ => return Ok(left),
+            };
+            let right = self.unary()?;
+            let (a, b) = (quantity(left)?, quantity(right)?);
+            left = CalcValue::Quantity(match op {
+                '*' => Quantity {
+                    num: a.num.mul(&b.num)?,
+                    dim: combine(a.dim, b.dim, 1),
+                },
+                '/' => Quantity {
+                    num: a.num.div(&b.num)?,
+                    dim: combine(a.dim, b.dim, -1),
+                },
+                _ => {
+                    if a.dim != b.dim {
+                        return Err("取余的两边单位不一致".to_string());
+                    }
+                    Quantity {
+                        num: a.num.rem(&b.num)?,
+                        dim: a.dim,
+                    }
+                }
+            });
+        }
+    }
+
+    fn unary(&mut self) -> Result<CalcValue, String> {
+        match self.peek() {
+            Some(Token::Op('-')) => {
+                self.pos += 1;
+                // `-3 C` 中的负号属于温度读数本身
+                if let (Some(Token::Num(_)), Some(Token::Ident(name))) =
+                    (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+                    && find_unit(name).is_some_and(|unit| unit.offset.is_some())
+                {
+                    return self.power(true);
+                }
+                let v = quantity(self.unary()?)?;
+                Ok(CalcValue::Quantity(Quantity {
+                    num: v.num.neg(),
+                    dim: v.dim,
+                }))
+            }
+            Some(Token::Op('+')) => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.power(false),
+        }
+    }
+
+    fn power(&mut self, neg: bool) -> Result<CalcValue, String> {
+        let base = self.postfix(neg)?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.pos += 1;
+            let exp = quantity(self.unary()?)?;
+            if !exp.is_dimensionless() {
+                return Err("指数不能带单位".to_string());
+            }
+            let base = quantity(base)?;
+            let dim = if base.is_dimensionless() {
+                DIMENSIONLESS
+            } else {
+                let e = exp
+                    .num
+                    .to_i64()
+                    .filter(|v| v.abs() <= 8)
+                    .ok_or("带单位的数只能进行整数次乘方")?;
+                scale(base.dim, e as i8)?
+            };
+            return Ok(CalcValue::Quantity(Quantity {
+                num: base.num.pow(&exp.num)?,
+                dim,
+            }));
+        }
+        Ok(base)
+    }
+
+    fn postfix(&mut self, neg: bool) -> Result<CalcValue, String> {
+        let mut v = self.primary(neg)?;
+        while let Some(Token::Op('!')) = self.peek() {
+            self.pos += 1;
+            v = CalcValue::Quantity(Quantity::number(factorial(&quantity(v)?)?));
+        }
+        Ok(v)
+    }
+
+    fn primary(&mut self, neg: bool) -> Result<CalcValue, String> {
+        match self.next() {
+            Some(Token::Num(s)) => {
+                let num = Num::parse(&s).ok_or_else(|| format!("无效的数字 `{}`", s))?;
+                let num = if neg { num.neg() } else { num };
+                // 温度读数需要加上零点偏移，如 `30 C` 为 303.15 K
+                if let Some(Token::Ident(name)) = self.peek()
+                    && let Some(unit) = find_unit(name).filter(|unit| unit.offset.is_some())
+                {
+                    self.pos += 1;
+                    let offset = Num::parse(unit.offset.unwrap()).unwrap();
+                    let v = Quantity {
+                        num: num.add(&offset)?.mul(&unit_factor(unit))?,
+                        dim: unit.dim,
+                    };
+                    // 开尔文没有偏移，可以直接运算
+                    return Ok(match unit.offset {
+                        Some("0") => CalcValue::Quantity(v),
+                        _ => CalcValue::Reading(v),
+                    });
+                }
+                Ok(CalcValue::Quantity(Quantity::number(num)))
+            }
+            Some(Token::Date(date)) => Ok(CalcValue::Date(date)),
+            Some(Token::LParen) => {
+                let v = self.sum()?;
+                self.expect(Token::RParen)?;
+                Ok(v)
+            }
+            Some(Token::Ident(name)) => {
+                if let Some(Token::LParen) = self.peek() {
+                    self.pos += 1;
+                    let mut args = vec![self.sum()?];
+                    while let Some(Token::Comma) = self.peek() {
+                        self.pos += 1;
+                        args.push(self.sum()?);
+                    }
+                    self.expect(Token::RParen)?;
+                    return call(&name, args);
+                }
+                self.ident(&name)
+            }
+            Some(token) => Err(format!("此处不应出现 {:?}", token)),
+            None => Err("表达式不完整".to_string()),
+        }
+    }
+
+    fn ident(&self, name: &str) -> Result<CalcValue, String> {
+        let number = |v: f64| Ok(CalcValue::Quantity(Quantity::number(Num::Approx(v))));
+        match name {
+            "pi" | "π" => number(std::f64::consts::PI),
+            "e" => number(std::f64::consts::E),
+            "now" | "现在" => Ok(CalcValue::Date(self.now)),
+            "today" | "今天" => Ok(CalcValue::Date(
+                self.now.date().and_hms_opt(0, 0, 0).unwrap(),
+            )),
+            _ => match find_unit(name) {
+                Some(unit) => Ok(CalcValue::Quantity(Quantity {
+                    num: unit_factor(unit),
+                    dim: unit.dim,
+                })),
+                None => Err(format!("未知的函数、常量或单位 `{}`", name)),
+            },
+        }
+    }
+}
+
+fn quantity(v: CalcValue) -> Result<Quantity, String> {
+    match v {
+        CalcValue::Quantity(v) => Ok(v),
+        CalcValue::Reading(_) => Err("温度读数只能换算，不能参与运算，温差请用 K 表示".to_string()),
+        CalcValue::Date(_) => Err("日期只能与时长相加减，或与日期相减".to_string()),
+    }
+}
+
+fn combine(a: Dim, b: Dim, sign: i8) -> Dim {
+    let mut dim = a;
+    for i in 0..dim.len() {
+        dim[i] += b[i] * sign;
+    }
+    dim
+}
+
+fn scale(dim: Dim, e: i8) -> Result<Dim, String> {
+    let mut res = DIMENSIONLESS;
+    for i in 0..dim.len() {
+        res[i] = dim[i].checked_mul(e).ok_or("单位的指数过大")?;
+    }
+    Ok(res)
+}
+
+fn add(left: CalcValue, right: CalcValue, sub: bool) -> Result<CalcValue, String> {
+    match (left, right) {
+        (CalcValue::Date(a), CalcValue::Date(b)) if sub => {
+            let secs = (a - b).num_seconds();
+            Ok(CalcValue::Quantity(Quantity {
+                num: Num::from_i64(secs),
+                dim: TIME,
+            }))
+        }
+        (CalcValue::Date(date), CalcValue::Quantity(d)) => {
+            if d.dim != TIME {
+                return Err("日期只能与时长相加减".to_string());
+            }
+            // 转换为整数前检查范围，避免过大的时长被截断为 i64::MAX
+            let ms = (d.num.to_f64() * 1000.0).round();
+            if !ms.is_finite() || ms.abs() >= i64::MAX as f64 {
+                return Err("时长过大".to_string());
+            }
+            let delta = TimeDelta::try_milliseconds(ms as i64).ok_or("时长过大")?;
+            let date = if sub {
+                date.checked_sub_signed(delta)
+            } else {
+                date.checked_add_signed(delta)
+            };
+            Ok(CalcValue::Date(date.ok_or("日期超出范围")?))
+        }
+        (a, b) => {
+            let (a, b) = (quantity(a)?, quantity(b)?);
+            if a.dim != b.dim {
+                return Err(format!(
+                    "单位不一致，无法相加减：{} 与 {}",
+                    dim_text(a.dim),
+                    dim_text(b.dim)
+                ));
+            }
+            let num = if sub {
+                a.num.sub(&b.num)?
+            } else {
+                a.num.add(&b.num)?
+            };
+            Ok(CalcValue::Quantity(Quantity { num, dim: a.dim }))
+        }
+    }
+}
+
+fn factorial(v: &Quantity) -> Result<Num, String> {
+    match v.num.to_i64() {
+        Some(n) if v.is_dimensionless() && (0..=3000).contains(&n) => {
+            let mut res = Rational::from_i64(1);
+            for i in 2..=n {
+                res = res.mul(&Rational::from_i64(i));
+            }
+            check_size(&res)?;
+            Ok(Num::Exact(res))
+        }
+        _ => Err("阶乘只支持 0 到 3000 的整数".to_string()),
+    }
+}
+
+fn call(name: &str, args: Vec<CalcValue>) -> Result<CalcValue, String> {
+    let args = args
+        .into_iter()
+        .map(quantity)
+        .collect::<Result<Vec<_>, _>>()?;
+    let arity = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(format!("函数 {} 需要 {} 个参数", name, n))
+        }
+    };
+    let number = |i: usize| -> Result<f64, String> {
+        let v: &Quantity = &args[i];
+        if !v.is_dimensionless() {
+            return Err(format!("函数 {} 的参数不能带单位", name));
+        }
+        Ok(v.num.to_f64())
+    };
+    let done = |num: Num, dim: Dim| Ok(CalcValue::Quantity(Quantity { num, dim }));
+    let approx = |v: f64| done(Num::approx(v)?, DIMENSIONLESS);
+
+    match name {
+        "abs" => {
+            arity(1)?;
+            let v = &args[0];
+            let num = match &v.num {
+                Num::Exact(r) if r.neg => Num::Exact(r.neg()),
+                Num::Approx(f) => Num::Approx(f.abs()),
+                num => num.clone(),
+            };
+            done(num, v.dim)
+        }
+        "floor" | "ceil" | "round" | "trunc" => {
+            arity(1)?;
+            let v = &args[0];
+            let num = match &v.num {
+                Num::Exact(r) => {
+                    let half = Rational::new(false, BigUint::from_u64(1), BigUint::from_u64(2));
+                    Num::Exact(match name {
+                        "floor" => r.floor(),
+                        "ceil" => r.neg().floor().neg(),
+                        "round" if r.neg => r.neg().add(&half).floor().neg(),
+                        "round" => r.add(&half).floor(),
+                        _ if r.neg => r.neg().floor().neg(),
+                        _ => r.floor(),
+                    })
+                }
+                Num::Approx(f) => Num::Approx(match name {
+                    "floor" => f.floor(),
+                    "ceil" => f.ceil(),
+                    "round" => f.round(),
+                    _ => f.trunc(),
+                }),
+            };
+            done(num, v.dim)
+        }
+        "sqrt" => {
+            arity(1)?;
+            let v = &args[0];
+            if v.dim.iter().any(|d| d % 2 != 0) {
+                return Err("带单位的数开平方后单位的指数必须是整数".to_string());
+            }
+            let f = v.num.to_f64();
+            if f < 0.0 {
+                return Err("负数不能开平方".to_string());
+            }
+            let dim = v.dim.map(|d| d / 2);
+            done(Num::approx(f.sqrt())?, dim)
+        }
+        "cbrt" => {
+            arity(1)?;
+            approx(number(0)?.cbrt())
+        }
+        "sin" => {
+            arity(1)?;
+            approx(number(0)?.sin())
+        }
+        "cos" => {
+            arity(1)?;
+            approx(number(0)?.cos())
+        }
+        "tan" => {
+            arity(1)?;
+            approx(number(0)?.tan())
+        }
+        "asin" => {
+            arity(1)?;
+            approx(number(0)?.asin())
+        }
+        "acos" => {
+            arity(1)?;
+            approx(number(0)?.acos())
+        }
+        "atan" => {
+            arity(1)?;
+            approx(number(0)?.atan())
+        }
+        "exp" => {
+            arity(1)?;
+            approx(number(0)?.exp())
+        }
+        "ln" => {
+            arity(1)?;
+            approx(number(0)?.ln())
+        }
+        "log2" => {
+            arity(1)?;
+            approx(number(0)?.log2())
+        }
+        "log" | "log10" => match args.len() {
+            1 => approx(number(0)?.log10()),
+            2 => approx(number(0)?.ln() / number(1)?.ln()),
+            _ => Err(format!("函数 {} 需要 1 或 2 个参数", name)),
+        },
+        "deg" => {
+            arity(1)?;
+            approx(number(0)?.to_degrees())
+        }
+        "rad" => {
+            arity(1)?;
+            approx(number(0)?.to_radians())
+        }
+        "max" | "min" => {
+            let first = args
+                .first()
+                .ok_or(format!("函数 {} 至少需要 1 个参数", name))?;
+            if args.iter().any(|v| v.dim != first.dim) {
+                return Err(format!("函数 {} 的参数单位不一致", name));
+            }
+            let pick = args
+                .iter()
+                .max_by(|a, b| {
+                    let ord = a.num.to_f64().total_cmp(&b.num.to_f64());
+                    if name == "max" { ord } else { ord.reverse() }
+                })
+                .unwrap();
+            done(pick.num.clone(), pick.dim)
+        }
+        _ => Err(format!("未知的函数 `{}`", name)),
+    }
+}
+
+fn dim_text(dim: Dim) -> String {
+    if dim == DIMENSIONLESS {
+        return "无单位".to_string();
+    }
+    let part = |sign: i8| {
+        dim.iter()
+            .zip(BASE_UNITS)
+            .filter(|(d, _)| **d * sign > 0)
+            .map(|(d, name)| match d.abs() {
+                1 => name.to_string(),
+                n => format!("{}^{}", name, n),
+            })
+            .collect::<Vec<_>>()
+            .join("·")
+    };
+    let (num, den) = (part(1), part(-1));
+    match (num.is_empty(), den.is_empty()) {
+        (_, true) => num,
+        (true, false) => format!("1/{}", den),
+        (false, false) => format!("{}/{}", num, den),
+    }
+}
+
+/// 时长转换为 `x 天 x 小时 x 分 x 秒`
+fn duration_text(secs: f64) -> String {
+    let sign = if secs < 0.0 { "-" } else { "" };
+    let mut rest = secs.abs().round() as u64;
+    let mut parts = Vec::new();
+    for (len, name) in [(86400, "天"), (3600, "小时"), (60, "分"), (1, "秒")] {
+        if rest >= len {
+            parts.push(format!("{} {}", rest / len, name));
+            rest %= len;
+        }
+    }
+    format!("{}{}", sign, parts.join(" "))
+}
+
+fn date_text(date: NaiveDateTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["一", "二", "三", "四", "五", "六", "日"];
+    let weekday = WEEKDAYS[date.weekday().num_days_from_monday() as usize];
+    if date.num_seconds_from_midnight() == 0 {
+        format!("{} 星期{}", date.format("%Y-%m-%d"), weekday)
+    } else {
+        format!("{} 星期{}", date.format("%Y-%m-%d %H:%M:%S"), weekday)
+    }
+}
+
+/// 计算表达式，返回结果文本
+pub fn evaluate(expr: &str) -> Result<String, String> {
+    evaluate_at(expr, chrono::Local::now().naive_local())
+}
+
+fn evaluate_at(expr: &str, now: NaiveDateTime) -> Result<String, String> {
+    let mut tokens = tokenize(expr)?;
+
+    // `to` 之后为换算的目标单位
+    let target = match tokens.iter().position(|token| *token == Token::To) {
+        Some(i) => {
+            let target = tokens.split_off(i + 1);
+            tokens.pop();
+            if target.is_empty() {
+                return Err("缺少换算的目标单位".to_string());
+            }
+            Some(target)
+        }
+        None => None,
+    };
+
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        now,
+    };
+    let value = parser.sum()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("此处不应出现 {:?}", token));
+    }
+
+    let value = match value {
+        CalcValue::Date(date) => return Ok(date_text(date)),
+        CalcValue::Quantity(v) | CalcValue::Reading(v) => v,
+    };
+
+    let Some(target) = target else {
+        return Ok(match value.dim {
+            DIMENSIONLESS => value.num.to_string(),
+            TIME if value.num.to_f64().abs() >= 60.0 => {
+                format!("{} s（{}）", value.num, duration_text(value.num.to_f64()))
+            }
+            DATA => format!("{} B", value.num.div(&Num::from_i64(8))?),
+            dim => format!("{} {}", value.num, dim_text(dim)),
+        });
+    };
+
+    let unit_text = target
+        .iter()
+        .map(|token| match token {
+            Token::Ident(v) | Token::Num(v) => v.clone(),
+            Token::Op(v) => v.to_string(),
+            Token::LParen => "(".to_string(),
+            Token::RParen => ")".to_string(),
+            _ => String::new(),
+        })
+        .collect::<String>();
+
+    // 温度换算需要减去零点偏移
+    if let [Token::Ident(name)] = target.as_slice()
+        && let Some(unit) = find_unit(name).filter(|unit| unit.offset.is_some())
+    {
+        if value.dim != TEMP {
+            return Err(format!(
+                "单位不一致：{} 无法换算为 {}",
+                dim_text(value.dim),
+                name
+            ));
+        }
+        let offset = Num::parse(unit.offset.unwrap()).unwrap();
+        let num = value.num.div(&unit_factor(unit))?.sub(&offset)?;
+        return Ok(format!("{} {}", num, unit_text));
+    }
+
+    let mut parser = Parser {
+        tokens: target,
+        pos: 0,
+        now,
+    };
+    let unit = quantity(parser.sum()?)?;
+    if parser.peek().is_some() {
+        return Err(format!("无效的目标单位 `{}`", unit_text));
+    }
+    if unit.dim != value.dim {
+        return Err(format!(
+            "单位不一致：{} 无法换算为 {}",
+            dim_text(value.dim),
+            dim_text(unit.dim)
+        ));
+    }
+    Ok(format!("{} {}", value.num.div(&unit.num)?, unit_text))
+}
+
+/// 让模型进行精确计算的工具
+pub struct CalcTool;
+
+impl Tool for CalcTool {
+    fn name(&self) -> &str {
+        "calculate"
+    }
+
+    fn description(&self) -> &str {
+        "精确计算数学表达式，涉及数值计算、单位换算或日期计算时应使用此工具，不要心算。\
+支持 + - * / % ^ ! 与括号，四则运算、整数乘方与阶乘为任意精度的精确计算；\
+函数 sqrt cbrt abs floor ceil round trunc sin cos tan asin acos atan exp ln log log2 deg rad max min，常量 pi e；\
+单位直接写在数字后，如 `3 km + 200 m to m`、`100 km/h to m/s`、`30 C to F`、`5 GiB to MB`，C 与 F 只能换算，温差用 K；\
+日期写作 2024-01-02 或 2024-01-02 03:04，可与时长相加减或相减得到间隔，如 `2024-12-25 - today`、`now + 90 days`。"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expression": {
+                    "type": "string",
+                    "description": "要计算的表达式",
+                },
+            },
+            "required": ["expression"],
+        })
+    }
+
+    fn execute(&self, args: Value) -> BoxFuture<'_, Result<String, String>> {
+        Box::pin(async move {
+            let expr = args["expression"]
+                .as_str()
+                .ok_or("缺少参数 expression")?
+                .to_string();
+            // 大数运算可能耗时较长，不占用异步运行时的线程
+            kovi::tokio::task::spawn_blocking(move || evaluate(&expr))
+                .await
+                .map_err(|err| err.to_string())?
+        })
+    }
+}
+
+#[test]
+fn test_arithmetic() {
+    assert_eq!(evaluate("1 + 2 * 3").unwrap(), "7");
+    assert_eq!(evaluate("(1 + 2) * 3").unwrap(), "9");
+    assert_eq!(evaluate("0.1 + 0.2").unwrap(), "0.3");
+    assert_eq!(evaluate("1 / 8").unwrap(), "0.125");
+    assert_eq!(
+        evaluate("1/3").unwrap(),
+        "1/3 ≈ 0.333333333333333333333333333333…"
+    );
+    assert_eq!(evaluate("-7 % 3").unwrap(), "2");
+    assert_eq!(evaluate("2 ^ -2").unwrap(), "0.25");
+    assert_eq!(evaluate("-2 ^ 2").unwrap(), "-4");
+    assert_eq!(
+        evaluate("2 ^ 100").unwrap(),
+        "1267650600228229401496703205376"
+    );
+    assert_eq!(evaluate("25!").unwrap(), "15511210043330985984000000");
+    assert_eq!(
+        evaluate("123456789123456789 * 987654321987654321").unwrap(),
+        "121932631356500531347203169112635269"
+    );
+    assert_eq!(
+        evaluate("99999999999999999999 / 3").unwrap(),
+        "33333333333333333333"
+    );
+    assert_eq!(evaluate("1e3 + 1.5e-1").unwrap(), "1000.15");
+    assert_eq!(evaluate("sqrt(16)").unwrap(), "4");
+    assert_eq!(evaluate("round(2.5) + floor(-1.5)").unwrap(), "1");
+    assert_eq!(evaluate("2(3 + 4)").unwrap(), "14");
+    assert_eq!(evaluate("max(1, 5, 3)").unwrap(), "5");
+    assert_eq!(
+        evaluate("(2^200 + 12345) % (3^50 + 7)").unwrap(),
+        "541240827069857667397609"
+    );
+    assert_eq!(
+        evaluate("floor((2^200 + 12345) / (3^50 + 7))").unwrap(),
+        "2238393297946874000179396464450271977"
+    );
+    assert!(evaluate("1 / 0").is_err());
+    assert!(evaluate("10 ^ 1000000").is_err());
+    assert!(evaluate("1 +").is_err());
+    assert!(evaluate("2 3").is_err());
+    assert!(evaluate("3000!").unwrap().ends_with("（共 9131 位）"));
+}
+
+#[test]
+fn test_units() {
+    assert_eq!(evaluate("3 km + 200 m to m").unwrap(), "3200 m");
+    assert_eq!(
+        evaluate("100 km/h to m/s").unwrap(),
+        "250/9 ≈ 27.777777777777777777777777777777… m/s"
+    );
+    assert_eq!(evaluate("30 C to F").unwrap(), "86 F");
+    assert_eq!(evaluate("-40 F to C").unwrap(), "-40 C");
+    assert_eq!(evaluate("(25 C) to K").unwrap(), "298.15 K");
+    assert_eq!(evaluate("10 K + 5 K").unwrap(), "15 K");
+    assert!(evaluate("10 C + 5 C to C").is_err());
+    assert!(evaluate("2 * 30 C").is_err());
+    assert!(evaluate("-3 C - 1 K").is_err());
+    assert_eq!(evaluate("1 GiB to MB").unwrap(), "1073.741824 MB");
+    assert_eq!(evaluate("3斤2两 to kg").unwrap(), "1.6 kg");
+    assert_eq!(
+        evaluate("1 亩 to m^2").unwrap(),
+        "2000/3 ≈ 666.666666666666666666666666666666… m^2"
+    );
+    assert_eq!(evaluate("2 m * 3 m").unwrap(), "6 m^2");
+    assert_eq!(evaluate("90 min").unwrap(), "5400 s（1 小时 30 分）");
+    assert_eq!(evaluate("5 ft 10 inch to cm").unwrap(), "177.8 cm");
+    assert_eq!(evaluate("1h 30min to min").unwrap(), "90 min");
+    assert_eq!(evaluate("1500 μm转mm").unwrap(), "1.5 mm");
+    assert!(evaluate("1 m + 1 s").is_err());
+    assert!(evaluate("1 m to kg").is_err());
+}
+
+#[test]
+fn test_dates() {
+    let now = NaiveDate::from_ymd_opt(2024, 3, 1)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    assert_eq!(
+        evaluate_at("2024-12-25 - 2024-01-01 to days", now).unwrap(),
+        "359 days"
+    );
+    assert_eq!(
+        evaluate_at("2024-01-31 + 30 days", now).unwrap(),
+        "2024-03-01 星期五"
+    );
+    assert_eq!(
+        evaluate_at("today + 1 week", now).unwrap(),
+        "2024-03-08 星期五"
+    );
+    assert_eq!(
+        evaluate_at("now - 2024-03-01 08:30", now).unwrap(),
+        "12600 s（3 小时 30 分）"
+    );
+}
+
+#[test]
+fn test_bigint() {
+    let big = |s: &str| Rational::parse(s).unwrap().num;
+
+    // 多位除数逐位估商时需要修正的情况
+    let a = big("1000000000000000000000000000");
+    let b = big("999999999999999999");
+    let (q, r) = a.divmod(&b);
+    assert_eq!(q.to_string(), "1000000000");
+    assert_eq!(r.to_string(), "1000000000");
+    assert_eq!(q.mul(&b).add(&r), a);
+    let (q, r) = b.divmod(&a);
+    assert!(q.is_zero());
+    assert_eq!(r, b);
+    let (q, r) = a.divmod(&a);
+    assert!(q.is_one() && r.is_zero());
+    let c = big("123456789012345678901234567890");
+    let d = big("1000000000000000001");
+    let (q, r) = c.divmod(&d);
+    assert!(r < d);
+    assert_eq!(q.mul(&d).add(&r), c);
+
+    assert_eq!(big("12").gcd(&BigUint::zero()).to_string(), "12");
+    assert_eq!(BigUint::zero().gcd(&big("12")).to_string(), "12");
+    assert_eq!(
+        big("2000000000000000000")
+            .gcd(&big("3000000000"))
+            .to_string(),
+        "1000000000"
+    );
+    assert_eq!(BigUint::zero().digits(), 0);
+    assert_eq!(big("1000000000").digits(), 10);
+}
+
+#[test]
+fn test_display_limits() {
+    // 分子分母过长时只显示近似值
+    assert_eq!(
+        evaluate("2 ^ -100").unwrap(),
+        "1/1267650600228229401496703205376 ≈ 7.88860905221011805411728565282…×10^-31"
+    );
+    assert_eq!(
+        evaluate("1 / 3 ^ 100").unwrap(),
+        "≈ 1.9403252174826328375885060288…×10^-48"
+    );
+    assert_eq!(
+        evaluate("2 ^ 200 / 3").unwrap(),
+        "≈ 535646014752996758513987364113720867507400997927597611767125.333333333333333333333333333333…"
+    );
+    assert_eq!(
+        evaluate("-1 / 10 ^ 20").unwrap(),
+        "-1/100000000000000000000 ≈ -1…×10^-20"
+    );
+    assert_eq!(
+        evaluate("10 ^ 2000 / 7").unwrap(),
+        "≈ 1.42857142857142857142857142857…×10^1999"
+    );
+    assert!(
+        evaluate("3 ^ 20000")
+            .unwrap()
+            .ends_with("×10^9542（共 9543 位）")
+    );
+    assert!(evaluate("3 ^ 30000").is_err());
+    assert!(evaluate("1e20000").is_err());
+}
+
+#[test]
+fn test_date_limits() {
+    let now = NaiveDate::from_ymd_opt(2024, 3, 1)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    assert_eq!(
+        evaluate_at("today + 1e20 days", now).unwrap_err(),
+        "时长过大"
+    );
+    assert_eq!(
+        evaluate_at("today + 1e6 years", now).unwrap_err(),
+        "日期超出范围"
+    );
+    assert_eq!(
+        evaluate_at("today - 1e6 years", now).unwrap_err(),
+        "日期超出范围"
+    );
+    assert!(evaluate_at("2024-02-30 + 1 day", now).is_err());
+    assert!(evaluate_at("today + 1 m", now).is_err());
+}
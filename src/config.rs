@@ -1,11 +1,822 @@
+use kovi::serde_json;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub(crate) apikey: Option<String>,
     pub(crate) base_url: Option<String>,
     pub(crate) model_name: Option<String>,
+    /// 主接口的格式
+    #[serde(default)]
+    pub(crate) provider: ProviderKind,
+    /// 主接口为 Ollama 时的参数
+    #[serde(default)]
+    pub(crate) ollama: OllamaConfig,
+    /// 所有接口共用的生成参数
+    #[serde(default)]
+    pub(crate) generation: GenerationConfig,
+    /// 主接口失败时依次尝试的备用接口
+    #[serde(default)]
+    pub(crate) fallbacks: Vec<ModelProfile>,
     pub(crate) cmd: char,
+    #[serde(default)]
+    pub(crate) render: RenderConfig,
+    /// 使用 `default_mode` 输出的调用符号，不设置时不启用。`cmd` 与双 `cmd` 始终分别使用图片与文本
+    #[serde(default)]
+    pub(crate) mode_cmd: Option<char>,
+    /// 以 `mode_cmd` 提问时的输出方式
+    #[serde(default)]
+    pub(crate) default_mode: OutputMode,
+    #[serde(default)]
+    pub(crate) auto: AutoConfig,
+    #[serde(default)]
+    pub(crate) text: TextConfig,
+    #[serde(default)]
+    pub(crate) file: FileConfig,
+    /// 图片模式下代码块的额外发送方式
+    #[serde(default)]
+    pub(crate) code_blocks: CodeBlockMode,
+    /// 按群号覆盖的配置
+    #[serde(default)]
+    pub(crate) groups: HashMap<i64, GroupConfig>,
+    #[serde(default)]
+    pub(crate) tools: ToolsConfig,
+    #[serde(default)]
+    pub(crate) history: HistoryConfig,
+    #[serde(default)]
+    pub(crate) summary: SummaryConfig,
+    #[serde(default)]
+    pub(crate) knowledge: KnowledgeConfig,
+    #[serde(default)]
+    pub(crate) cache: CacheConfig,
+    #[serde(default)]
+    pub(crate) request: RequestConfig,
+    #[serde(default)]
+    pub(crate) reasoning: ReasoningConfig,
+    #[serde(default)]
+    pub(crate) search: SearchConfig,
+    #[serde(default)]
+    pub(crate) fetch: FetchConfig,
+    #[serde(default)]
+    pub(crate) indicator: IndicatorConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            apikey: None,
+            base_url: None,
+            model_name: None,
+            provider: ProviderKind::default(),
+            ollama: OllamaConfig::default(),
+            generation: GenerationConfig::default(),
+            fallbacks: Vec::new(),
+            cmd: '%',
+            render: RenderConfig::default(),
+            mode_cmd: None,
+            default_mode: OutputMode::default(),
+            auto: AutoConfig::default(),
+            text: TextConfig::default(),
+            file: FileConfig::default(),
+            code_blocks: CodeBlockMode::default(),
+            groups: HashMap::new(),
+            tools: ToolsConfig::default(),
+            history: HistoryConfig::default(),
+            summary: SummaryConfig::default(),
+            knowledge: KnowledgeConfig::default(),
+            cache: CacheConfig::default(),
+            request: RequestConfig::default(),
+            reasoning: ReasoningConfig::default(),
+            search: SearchConfig::default(),
+            fetch: FetchConfig::default(),
+            indicator: IndicatorConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    fn group(&self, group_id: Option<i64>) -> Option<&GroupConfig> {
+        self.groups.get(&group_id?)
+    }
+
+    pub(crate) fn text_format(&self, group_id: Option<i64>) -> TextFormat {
+        self.group(group_id)
+            .and_then(|group| group.text_format)
+            .unwrap_or(self.text.format)
+    }
+
+    /// 主接口与备用接口，按尝试顺序排列
+    pub(crate) fn profiles(&self) -> Vec<ModelProfile> {
+        let primary = ModelProfile {
+            name: None,
+            base_url: self.base_url.clone().unwrap(),
+            apikey: self.apikey.clone(),
+            model_name: self.model_name.clone().unwrap(),
+            provider: self.provider,
+            ollama: self.ollama.clone(),
+            generation: GenerationConfig::default(),
+        };
+        std::iter::once(primary)
+            .chain(self.fallbacks.iter().cloned())
+            .collect()
+    }
+
+    pub(crate) fn code_blocks(&self, group_id: Option<i64>) -> CodeBlockMode {
+        self.group(group_id)
+            .and_then(|group| group.code_blocks)
+            .unwrap_or(self.code_blocks)
+    }
+}
+
+/// 一个模型接口
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ModelProfile {
+    /// 日志与状态中显示的名称，默认为 `模型名@主机`
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+    pub(crate) base_url: String,
+    #[serde(default)]
+    pub(crate) apikey: Option<String>,
+    pub(crate) model_name: String,
+    #[serde(default)]
+    pub(crate) provider: ProviderKind,
+    #[serde(default)]
+    pub(crate) ollama: OllamaConfig,
+    /// 覆盖全局 `generation` 中的项
+    #[serde(default)]
+    pub(crate) generation: GenerationConfig,
+}
+
+/// 模型接口的格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// OpenAI 兼容的 `/chat/completions`
+    #[default]
+    OpenAI,
+    /// Anthropic 原生的 `/messages`
+    Anthropic,
+    /// Ollama 原生的 `/api/chat`，`base_url` 不带 `/v1`
+    Ollama,
+}
+
+/// Ollama 的请求参数，未设置的项使用 Ollama 的默认值
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OllamaConfig {
+    /// 请求后模型在内存中保留的时间，如 `5m`、`1h`，`-1` 为一直保留
+    pub(crate) keep_alive: Option<String>,
+    /// 上下文长度
+    pub(crate) num_ctx: Option<u32>,
+    /// 其他模型参数，如 `temperature`、`top_p`
+    pub(crate) options: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ModelProfile {
+    pub(crate) fn display_name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let host = self
+            .base_url
+            .split("://")
+            .last()
+            .unwrap_or_default()
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        format!("{}@{}", self.model_name, host)
+    }
+}
+
+/// 生成参数，未设置的项使用模型接口的默认值
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GenerationConfig {
+    /// 0 到 2，越高回答越随机
+    pub(crate) temperature: Option<f32>,
+    /// 0 到 1，核采样的概率阈值
+    pub(crate) top_p: Option<f32>,
+    /// 回答的最大 token 数
+    pub(crate) max_tokens: Option<u32>,
+    /// 遇到这些文本时停止生成，最多 4 个
+    pub(crate) stop: Vec<String>,
+    /// 随机种子，部分接口支持
+    pub(crate) seed: Option<i64>,
+    /// 推理模型的思考程度
+    pub(crate) reasoning_effort: Option<ReasoningEffort>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Minimal,
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ReasoningEffort::Minimal => "minimal",
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Option<ReasoningEffort> {
+        match s {
+            "minimal" => Some(ReasoningEffort::Minimal),
+            "low" => Some(ReasoningEffort::Low),
+            "medium" => Some(ReasoningEffort::Medium),
+            "high" => Some(ReasoningEffort::High),
+            _ => None,
+        }
+    }
+}
+
+impl GenerationConfig {
+    /// 用 `other` 中设置了的项覆盖自身
+    pub(crate) fn merge(&self, other: &GenerationConfig) -> GenerationConfig {
+        GenerationConfig {
+            temperature: other.temperature.or(self.temperature),
+            top_p: other.top_p.or(self.top_p),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            stop: if other.stop.is_empty() {
+                self.stop.clone()
+            } else {
+                other.stop.clone()
+            },
+            seed: other.seed.or(self.seed),
+            reasoning_effort: other.reasoning_effort.or(self.reasoning_effort),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == GenerationConfig::default()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(v) = self.temperature
+            && !(0.0..=2.0).contains(&v)
+        {
+            return Err(format!("temperature 应在 0 到 2 之间，当前为 {}", v));
+        }
+        if let Some(v) = self.top_p
+            && !(v > 0.0 && v <= 1.0)
+        {
+            return Err(format!("top_p 应大于 0 且不超过 1，当前为 {}", v));
+        }
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens 应大于 0".to_string());
+        }
+        if self.stop.len() > 4 {
+            return Err(format!("stop 最多 4 个，当前为 {} 个", self.stop.len()));
+        }
+        if self.stop.iter().any(String::is_empty) {
+            return Err("stop 不能为空文本".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// 单个群的配置，未设置的项使用全局配置
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GroupConfig {
+    pub(crate) text_format: Option<TextFormat>,
+    pub(crate) code_blocks: Option<CodeBlockMode>,
+}
+
+/// 图片渲染时附加在回答上下方的信息，全部默认关闭
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RenderConfig {
+    /// 页眉显示提问内容
+    pub(crate) show_question: bool,
+    /// 页眉中提问内容的最大字符数，超出部分用省略号代替
+    pub(crate) question_max_chars: usize,
+    /// 页眉显示提问者的昵称与头像
+    pub(crate) show_sender: bool,
+    /// 以聊天气泡的形式在回答上方显示提问与引用的消息
+    pub(crate) show_chat_card: bool,
+    /// 聊天气泡中引用消息的最大字符数
+    pub(crate) quote_max_chars: usize,
+    /// 页脚显示模型名
+    pub(crate) show_model: bool,
+    /// 页脚显示 token 用量
+    pub(crate) show_usage: bool,
+    /// 页脚显示请求耗时
+    pub(crate) show_latency: bool,
+    /// 页脚显示回答时间
+    pub(crate) show_time: bool,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            show_question: false,
+            question_max_chars: 60,
+            show_sender: false,
+            show_chat_card: false,
+            quote_max_chars: 200,
+            show_model: false,
+            show_usage: false,
+            show_latency: false,
+            show_time: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    #[default]
+    Image,
+    Text,
+    /// 根据回答内容自动选择，见 [`AutoConfig`]
+    Auto,
+    /// 上传为文件，见 [`FileConfig`]
+    File,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    #[default]
+    Html,
+    Pdf,
+}
+
+/// 以文件形式发送完整回答
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FileConfig {
+    pub(crate) format: FileFormat,
+    /// 自动模式下回答超过此字符数时上传为文件，为 0 时不自动使用文件
+    pub(crate) threshold: usize,
+    /// 聊天中发送的摘要的最大字符数
+    pub(crate) summary_chars: usize,
+}
+
+impl Default for FileConfig {
+    fn default() -> Self {
+        FileConfig {
+            format: FileFormat::default(),
+            threshold: 0,
+            summary_chars: 100,
+        }
+    }
+}
+
+/// 自动模式下，回答满足任意一条规则时使用图片，否则使用文本
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AutoConfig {
+    /// 超过此字符数使用图片
+    pub(crate) max_chars: usize,
+    /// 超过此行数使用图片
+    pub(crate) max_lines: usize,
+    /// 含有代码块时使用图片
+    pub(crate) image_on_code: bool,
+    /// 含有表格时使用图片
+    pub(crate) image_on_table: bool,
+    /// 含有数学公式时使用图片
+    pub(crate) image_on_math: bool,
+}
+
+impl Default for AutoConfig {
+    fn default() -> Self {
+        AutoConfig {
+            max_chars: 300,
+            max_lines: 12,
+            image_on_code: true,
+            image_on_table: true,
+            image_on_math: true,
+        }
+    }
+}
+
+impl AutoConfig {
+    pub(crate) fn prefers_image(&self, features: &crate::markdown::Features) -> bool {
+        features.chars > self.max_chars
+            || features.lines > self.max_lines
+            || (self.image_on_code && features.code_block)
+            || (self.image_on_table && features.table)
+            || (self.image_on_math && features.math)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextFormat {
+    /// 模型返回的 markdown 原文
+    #[default]
+    Raw,
+    /// 转换为纯文本
+    Plain,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeBlockMode {
+    /// 不额外发送
+    #[default]
+    Off,
+    /// 每个代码块作为一条文本消息发送
+    Text,
+    /// 每个代码块作为文件上传
+    File,
+}
+
+/// 文本模式的发送方式
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TextConfig {
+    /// 发送原始 markdown 还是转换后的纯文本
+    pub(crate) format: TextFormat,
+    /// 回答超过此字符数时切分后以合并转发发送，为 0（默认）时不使用合并转发
+    pub(crate) forward_threshold: usize,
+    /// 合并转发中每条消息的最大字符数
+    pub(crate) chunk_chars: usize,
+    /// 合并转发中显示的发送者名称
+    pub(crate) forward_name: String,
+}
+
+impl Default for TextConfig {
+    fn default() -> Self {
+        TextConfig {
+            format: TextFormat::default(),
+            forward_threshold: 0,
+            chunk_chars: 600,
+            forward_name: "aiqa".to_string(),
+        }
+    }
+}
+
+/// 模型调用工具的设置
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToolsConfig {
+    /// 一次提问中最多进行的工具调用轮数，超过后要求模型直接回答
+    pub(crate) max_iterations: usize,
+    /// 提供本地计算器工具，需要模型支持 function calling，默认关闭
+    pub(crate) calculator: bool,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        ToolsConfig {
+            max_iterations: 5,
+            calculator: false,
+        }
+    }
+}
+
+/// 网页搜索工具，默认关闭
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    pub(crate) enabled: bool,
+    pub(crate) provider: SearchProviderKind,
+    /// SearXNG 实例的地址，或 JSON 搜索接口的完整地址
+    pub(crate) url: String,
+    pub(crate) apikey: Option<String>,
+    /// 一次搜索返回给模型的最大结果数
+    pub(crate) max_results: usize,
+    /// 每条结果摘要的最大字符数
+    pub(crate) snippet_max_chars: usize,
+    pub(crate) timeout_secs: u64,
+    /// `provider` 为 `json` 时的接口格式
+    pub(crate) json: JsonSearchConfig,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            enabled: false,
+            provider: SearchProviderKind::default(),
+            url: String::new(),
+            apikey: None,
+            max_results: 5,
+            snippet_max_chars: 300,
+            timeout_secs: 10,
+            json: JsonSearchConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchProviderKind {
+    /// SearXNG 的 `/search?format=json`，实例需要开启 json 格式
+    #[default]
+    SearXNG,
+    /// 返回 JSON 的任意 GET 搜索接口
+    Json,
+}
+
+/// 通用 JSON 搜索接口的格式
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JsonSearchConfig {
+    /// 搜索词的查询参数名
+    pub(crate) query_param: String,
+    /// 其他固定的查询参数
+    pub(crate) params: HashMap<String, String>,
+    /// apikey 所在的请求头，为 `Authorization` 时使用 `Bearer` 格式
+    pub(crate) apikey_header: String,
+    /// 结果数组在响应中的路径，用 `.` 分隔
+    pub(crate) results_path: String,
+    pub(crate) title_field: String,
+    pub(crate) url_field: String,
+    pub(crate) snippet_field: String,
+}
+
+impl Default for JsonSearchConfig {
+    fn default() -> Self {
+        JsonSearchConfig {
+            query_param: "q".to_string(),
+            params: HashMap::new(),
+            apikey_header: "Authorization".to_string(),
+            results_path: "results".to_string(),
+            title_field: "title".to_string(),
+            url_field: "url".to_string(),
+            snippet_field: "snippet".to_string(),
+        }
+    }
+}
+
+/// 处理消息时在原消息上的表情回应，默认关闭。表情 id 为 QQ 表情的编号或 emoji 的 Unicode 码位，
+/// 如 `124`（OK）、`10060`（❌），为空时不回应
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IndicatorConfig {
+    pub(crate) enabled: bool,
+    /// 处理中，完成后移除
+    pub(crate) processing: String,
+    pub(crate) success: String,
+    pub(crate) failure: String,
+    /// 无法回应表情时（如私聊）的替代方式
+    pub(crate) fallback: IndicatorFallback,
+    /// `fallback` 为 `text` 时开始处理时发送的文本
+    pub(crate) text: String,
+}
+
+impl Default for IndicatorConfig {
+    fn default() -> Self {
+        IndicatorConfig {
+            enabled: false,
+            processing: "424".to_string(),
+            success: "124".to_string(),
+            failure: "10060".to_string(),
+            fallback: IndicatorFallback::default(),
+            text: "思考中…".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndicatorFallback {
+    None,
+    /// 显示“对方正在输入”，Milky 不支持，不提示
+    #[default]
+    Typing,
+    /// 发送一条简短的文本
+    Text,
+}
+
+/// 读取问题与引用消息中的链接，默认关闭
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FetchConfig {
+    pub(crate) enabled: bool,
+    /// 允许读取的域名，包含其子域名。为空时不限制
+    pub(crate) allowed_domains: Vec<String>,
+    /// 一次提问中最多读取的链接数
+    pub(crate) max_urls: usize,
+    /// 网页的最大字节数，超出部分丢弃
+    pub(crate) max_bytes: usize,
+    pub(crate) timeout_secs: u64,
+    /// 每个网页提取出的正文附带给模型的最大字符数
+    pub(crate) max_chars: usize,
+    /// 用无头浏览器加载网页，可以读取需要运行 JS 的页面
+    pub(crate) browser: bool,
+    /// 允许读取本机与内网地址
+    pub(crate) allow_private: bool,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            enabled: false,
+            allowed_domains: Vec::new(),
+            max_urls: 2,
+            max_bytes: 2_000_000,
+            timeout_secs: 10,
+            max_chars: 6000,
+            browser: false,
+            allow_private: false,
+        }
+    }
+}
+
+/// 群聊历史查询，涉及群友隐私，默认关闭
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub(crate) enabled: bool,
+    /// 允许查询的群，为空时所有群都允许
+    pub(crate) allowed_groups: Vec<i64>,
+    /// 这些用户的消息不会提供给模型
+    pub(crate) exclude_users: Vec<i64>,
+    /// 一次最多获取的消息条数
+    pub(crate) max_messages: usize,
+    /// 只提供最近多少分钟内的消息，为 0 时不限制
+    pub(crate) max_minutes: u64,
+    /// 每条消息的最大字符数
+    pub(crate) message_max_chars: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: false,
+            allowed_groups: Vec::new(),
+            exclude_users: Vec::new(),
+            max_messages: 100,
+            max_minutes: 24 * 60,
+            message_max_chars: 200,
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub(crate) fn allows(&self, group_id: i64) -> bool {
+        self.enabled && (self.allowed_groups.is_empty() || self.allowed_groups.contains(&group_id))
+    }
+}
+
+/// `%summary` 群聊总结命令，同样受 [`HistoryConfig`] 的隐私设置限制
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SummaryConfig {
+    /// 未指定条数时总结的消息条数
+    pub(crate) default_messages: usize,
+    /// 一次最多总结的消息条数
+    pub(crate) max_messages: usize,
+    /// 聊天记录超过此字符数时分段总结后再合并
+    pub(crate) chunk_chars: usize,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        SummaryConfig {
+            default_messages: 100,
+            max_messages: 500,
+            chunk_chars: 8000,
+        }
+    }
+}
+
+/// 本地知识库，文件放在数据目录的 `knowledge/` 下，默认关闭
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KnowledgeConfig {
+    pub(crate) enabled: bool,
+    /// 切分文件时每个片段的最大字符数
+    pub(crate) chunk_chars: usize,
+    /// 每次提问最多附带的片段数
+    pub(crate) top_k: usize,
+    /// 附带片段的总字符数上限
+    pub(crate) max_chars: usize,
+    /// 设置后同时使用 embedding 检索，使用第一个支持 embedding 的接口
+    pub(crate) embedding_model: Option<String>,
+    /// 混合检索中 embedding 相似度所占的权重，0 到 1
+    pub(crate) embedding_weight: f64,
+    /// 没有关键词匹配时，embedding 相似度低于此值的片段不使用
+    pub(crate) min_similarity: f64,
+}
+
+impl Default for KnowledgeConfig {
+    fn default() -> Self {
+        KnowledgeConfig {
+            enabled: false,
+            chunk_chars: 500,
+            top_k: 3,
+            max_chars: 1500,
+            embedding_model: None,
+            embedding_weight: 0.5,
+            min_similarity: 0.5,
+        }
+    }
+}
+
+/// 回答缓存，问题以 `-nocache` 开头时跳过缓存
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub(crate) enabled: bool,
+    /// 缓存的有效时间，秒
+    pub(crate) ttl_secs: u64,
+    /// 最多缓存的回答数，超出时淘汰最早的
+    pub(crate) max_entries: usize,
+    /// 缓存图片的总字节数，超出时丢弃最久没有使用的图片
+    pub(crate) max_bytes: usize,
+    /// 每个群（私聊为每个用户）使用独立的缓存
+    pub(crate) per_group: bool,
+    /// 设置后问题不完全相同时按 embedding 相似度查找缓存
+    pub(crate) embedding_model: Option<String>,
+    /// embedding 相似度不低于此值时视为相同的问题
+    pub(crate) similarity: f64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: false,
+            ttl_secs: 24 * 60 * 60,
+            max_entries: 500,
+            max_bytes: 64 * 1024 * 1024,
+            per_group: true,
+            embedding_model: None,
+            similarity: 0.95,
+        }
+    }
+}
+
+/// 请求模型接口的超时与重试
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RequestConfig {
+    /// 一次回答的总超时，包括重试、换用备用接口与工具调用，秒
+    pub(crate) timeout_secs: u64,
+    /// 单次请求的超时，秒
+    pub(crate) attempt_timeout_secs: u64,
+    /// 建立连接的超时，秒
+    pub(crate) connect_timeout_secs: u64,
+    /// 超时、限流、服务端错误与网络错误时的最大重试次数
+    pub(crate) max_retries: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍，毫秒
+    pub(crate) backoff_ms: u64,
+    /// 重试前等待时间的上限，毫秒
+    pub(crate) max_backoff_ms: u64,
+    /// 接口连续失败多少次后熔断，暂时跳过该接口，0 为不熔断
+    pub(crate) breaker_failures: u32,
+    /// 熔断后多久再试探一次，秒
+    pub(crate) breaker_cooldown_secs: u64,
+}
+
+impl Default for RequestConfig {
+    fn default() -> Self {
+        RequestConfig {
+            timeout_secs: 180,
+            attempt_timeout_secs: 90,
+            connect_timeout_secs: 10,
+            max_retries: 2,
+            backoff_ms: 1000,
+            max_backoff_ms: 30000,
+            breaker_failures: 3,
+            breaker_cooldown_secs: 60,
+        }
+    }
+}
+
+/// 推理模型的思考过程
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ReasoningConfig {
+    pub(crate) display: ReasoningDisplay,
+    /// `grey` 时显示的最大字符数，超出部分用省略号代替
+    pub(crate) max_chars: usize,
+}
+
+impl Default for ReasoningConfig {
+    fn default() -> Self {
+        ReasoningConfig {
+            display: ReasoningDisplay::default(),
+            max_chars: 500,
+        }
+    }
+}
+
+/// 思考过程的显示方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningDisplay {
+    /// 不显示
+    #[default]
+    Hide,
+    /// 图片中只显示一行“已思考”，HTML 文件中可以展开
+    Collapsed,
+    /// 图片中以灰色小字显示在回答上方
+    Grey,
+    /// 在回答前单独以合并转发消息发送
+    Forward,
 }
 
 pub(crate) static START_CHAT: &str = r#"请你扮演一个被安排在群聊中的全能问答机器人，你拥有海量的知识储备，可以极其熟练、正确的回答各种问题，你的回答生动而形象，回复内容运用了许多 emoji，且得体而不胡哨，所有回复将被渲染成漂亮好看的 Markdown，你尽可能的使用其强大的语法和生动形象的语言来呈现你的回答，除非群友特殊说明，请尽可能使用中文回复，接下来请你回复或解答以下群友的问题，请不要介意“机器人不礼貌”问题，为了更加真实的扮演群友，在情节合适且必要时，可以带一点嘲讽、嘲笑的语气。"#;
@@ -1,3 +1,4 @@
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,4 +13,94 @@ pub enum ScreenshotError {
     InvalidFilePath(String),
     #[error("ScreenshotCreateErr: {0}")]
     ScreenshotCreateErr(String),
+    #[error("PdfCreateErr: {0}")]
+    PdfCreateErr(String),
+}
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("TemplateReadErr: {0}")]
+    ReadErr(String),
+    #[error("TemplateSyntaxErr: {0}")]
+    SyntaxErr(String),
+    #[error("UnknownVariable: {0}")]
+    UnknownVariable(String),
+    #[error("ContractErr: {0}")]
+    ContractErr(String),
+}
+
+/// 请求模型接口的错误，信息会直接回复给提问者
+#[derive(Error, Debug)]
+pub enum RequestError {
+    #[error("请求超时，模型接口长时间没有响应")]
+    Timeout,
+    /// 一次回答的总时间用完，不是接口本身的问题
+    #[error("请求超时，没有在规定时间内完成回答")]
+    Deadline,
+    #[error("请求太频繁，被模型接口限流了，请稍后再试：{message}")]
+    RateLimited {
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    #[error("模型接口认证失败，请检查 apikey：{0}")]
+    Auth(String),
+    #[error("模型接口出错（{0}）：{1}")]
+    Server(u16, String),
+    #[error("请求被模型接口拒绝（{0}）：{1}")]
+    Api(u16, String),
+    #[error("无法连接模型接口：{0}")]
+    Network(String),
+    #[error("无法解析模型接口的响应：{0}")]
+    Decode(String),
+}
+
+impl RequestError {
+    pub fn from_reqwest(err: reqwest::Error) -> RequestError {
+        if err.is_timeout() {
+            RequestError::Timeout
+        } else if err.is_decode() {
+            RequestError::Decode(err.to_string())
+        } else {
+            let mut message = err.to_string();
+            if let Some(source) = std::error::Error::source(&err) {
+                message.push_str(&format!(": {}", source));
+            }
+            RequestError::Network(message)
+        }
+    }
+
+    /// 超时、限流、服务端错误与网络错误可以重试
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            RequestError::Timeout
+                | RequestError::RateLimited { .. }
+                | RequestError::Server(..)
+                | RequestError::Network(_)
+        )
+    }
+
+    /// 接口本身出了问题，计入熔断并换用备用接口，
+    /// 包括找不到模型或路径（404、405）与无法解析的响应。
+    /// 其余错误由请求内容导致，换接口也无济于事
+    pub fn failover(&self) -> bool {
+        self.retryable()
+            || matches!(
+                self,
+                RequestError::Auth(_) | RequestError::Decode(_) | RequestError::Api(404 | 405, _)
+            )
+    }
+}
+
+/// 问题开头的参数有误，信息会直接回复给提问者
+#[derive(Error, Debug, PartialEq)]
+pub enum FlagError {
+    #[error("参数 `{flag}` 的值 `{value}` 无效，应为{expected}")]
+    Invalid {
+        flag: String,
+        value: String,
+        expected: &'static str,
+    },
+    #[error("参数无效：{0}")]
+    OutOfRange(String),
 }
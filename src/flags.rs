@@ -0,0 +1,166 @@
+//! 问题开头的参数，如 `%-nocache 问题`、`%-t0.2 问题`
+//!
+//! 参数以 `-` 加字母开头，多个参数用空白分隔。遇到不认识的参数时停止解析，
+//! 其余内容都视为问题，因此 `%-1+2等于几` 之类的问题不受影响。
+//!
+//! 生成参数可以写成短形式 `-t0.2`、`-p0.9`、`-m500`、`-s42`，
+//! 或长形式 `-temperature=0.2`、`-top_p=0.9`、`-max_tokens=500`、`-seed=42`、`-stop=文本`，
+//! 推理模型的思考程度为 `-effort=high`。
+
+use crate::config::{GenerationConfig, ReasoningEffort};
+use crate::error::FlagError;
+
+/// 参数有误时附在错误信息后的说明
+pub const HELP: &str = "可用参数：-t0.2 温度（0 到 2），-p0.9 top_p（0 到 1），-m500 最大 token 数，-s42 随机种子，-stop=文本 停止生成的文本，-effort=high 思考程度（minimal、low、medium、high），-nocache 跳过回答缓存";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Flags {
+    /// 跳过回答缓存
+    pub no_cache: bool,
+    /// 覆盖配置中的生成参数
+    pub generation: GenerationConfig,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Param {
+    Temperature,
+    TopP,
+    MaxTokens,
+    Seed,
+    Stop,
+    ReasoningEffort,
+}
+
+/// 解析问题开头的参数，返回参数与剩余的问题
+pub fn parse(text: &str) -> Result<(Flags, &str), FlagError> {
+    let mut flags = Flags::default();
+    let mut rest = text.trim_start();
+
+    while let Some(token) = rest.split_whitespace().next() {
+        let Some(name) = token
+            .strip_prefix('-')
+            .filter(|v| v.starts_with(|c: char| c.is_ascii_alphabetic()))
+        else {
+            break;
+        };
+        match name {
+            "nocache" => flags.no_cache = true,
+            _ => match generation_param(name) {
+                Some((param, value)) => set(&mut flags.generation, param, token, value)?,
+                None => break,
+            },
+        }
+        rest = rest[token.len()..].trim_start();
+    }
+
+    flags.generation.validate().map_err(FlagError::OutOfRange)?;
+    Ok((flags, rest))
+}
+
+/// 识别生成参数，短形式的值必须以数字、`.` 或 `-` 开头，以免把 `-top 是什么` 之类的问题当作参数
+fn generation_param(name: &str) -> Option<(Param, &str)> {
+    if let Some((key, value)) = name.split_once('=') {
+        let param = match key {
+            "t" | "temperature" => Param::Temperature,
+            "p" | "top_p" => Param::TopP,
+            "m" | "max_tokens" => Param::MaxTokens,
+            "s" | "seed" => Param::Seed,
+            "stop" => Param::Stop,
+            "effort" | "reasoning_effort" => Param::ReasoningEffort,
+            _ => return None,
+        };
+        return Some((param, value));
+    }
+
+    let (key, value) = name.split_at(1);
+    if !value.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+        return None;
+    }
+    let param = match key {
+        "t" => Param::Temperature,
+        "p" => Param::TopP,
+        "m" => Param::MaxTokens,
+        "s" => Param::Seed,
+        _ => return None,
+    };
+    Some((param, value))
+}
+
+fn set(
+    generation: &mut GenerationConfig,
+    param: Param,
+    flag: &str,
+    value: &str,
+) -> Result<(), FlagError> {
+    let invalid = |expected| FlagError::Invalid {
+        flag: flag.to_string(),
+        value: value.to_string(),
+        expected,
+    };
+    match param {
+        Param::Temperature => {
+            generation.temperature = Some(value.parse().map_err(|_| invalid("0 到 2 之间的数字"))?)
+        }
+        Param::TopP => {
+            generation.top_p = Some(value.parse().map_err(|_| invalid("0 到 1 之间的数字"))?)
+        }
+        Param::MaxTokens => {
+            generation.max_tokens = Some(value.parse().map_err(|_| invalid("正整数"))?)
+        }
+        Param::Seed => generation.seed = Some(value.parse().map_err(|_| invalid("整数"))?),
+        Param::Stop if value.is_empty() => return Err(invalid("非空文本")),
+        Param::Stop => generation.stop.push(value.to_string()),
+        Param::ReasoningEffort => {
+            generation.reasoning_effort = Some(
+                ReasoningEffort::parse(value)
+                    .ok_or_else(|| invalid("minimal、low、medium 或 high"))?,
+            )
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse() {
+    let no_cache = Flags {
+        no_cache: true,
+        ..Flags::default()
+    };
+    assert_eq!(parse("-nocache 你好"), Ok((no_cache.clone(), "你好")));
+    assert_eq!(parse("  -nocache"), Ok((no_cache, "")));
+    assert_eq!(parse("-1+2等于几"), Ok((Flags::default(), "-1+2等于几")));
+    assert_eq!(
+        parse("-rust 是什么"),
+        Ok((Flags::default(), "-rust 是什么"))
+    );
+    assert_eq!(parse("-t 是什么"), Ok((Flags::default(), "-t 是什么")));
+    assert_eq!(
+        parse("你好 -nocache"),
+        Ok((Flags::default(), "你好 -nocache"))
+    );
+
+    let (flags, rest) = parse("-t0.2 -m=500 -stop=END -seed=42 -effort=low 写一首诗").unwrap();
+    assert_eq!(rest, "写一首诗");
+    assert_eq!(flags.generation.temperature, Some(0.2));
+    assert_eq!(flags.generation.max_tokens, Some(500));
+    assert_eq!(flags.generation.stop, vec!["END"]);
+    assert_eq!(flags.generation.seed, Some(42));
+    assert_eq!(
+        flags.generation.reasoning_effort,
+        Some(ReasoningEffort::Low)
+    );
+    assert!(matches!(
+        parse("-effort=max 你好"),
+        Err(FlagError::Invalid { .. })
+    ));
+
+    assert!(matches!(
+        parse("-m1.5 你好"),
+        Err(FlagError::Invalid {
+            expected: "正整数",
+            ..
+        })
+    ));
+    assert!(matches!(parse("-t3 你好"), Err(FlagError::OutOfRange(_))));
+    assert!(matches!(parse("-p0 你好"), Err(FlagError::OutOfRange(_))));
+}
@@ -0,0 +1,581 @@
+//! 群聊历史消息的获取与格式化
+
+use crate::config::HistoryConfig;
+use crate::tools::Tool;
+use kovi::RuntimeBot;
+use kovi::chrono::{self, TimeZone as _};
+use kovi::futures_util::future::BoxFuture;
+use kovi::log;
+use kovi::serde_json::{Value, json};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct HistoryMessage {
+    pub user_id: i64,
+    pub nickname: String,
+    /// unix 时间戳，秒
+    pub time: i64,
+    pub text: String,
+}
+
+/// 获取群内最近的 `count` 条消息，按时间从早到晚排列
+#[cfg(feature = "napcat-onebot")]
+pub async fn fetch_group_history(
+    bot: &RuntimeBot,
+    group_id: i64,
+    count: usize,
+) -> Result<Vec<HistoryMessage>, String> {
+    let res = bot
+        .send_api_return(
+            "get_group_msg_history",
+            json!({
+                "group_id": group_id,
+                "count": count,
+            }),
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let mut msgs: Vec<HistoryMessage> = res.data["messages"]
+        .as_array()
+        .ok_or("get_group_msg_history: no messages")?
+        .iter()
+        .filter_map(onebot_message)
+        .collect();
+
+    msgs.sort_by_key(|msg| msg.time);
+    let skip = msgs.len().saturating_sub(count);
+    Ok(msgs.split_off(skip))
+}
+
+/// OneBot 的消息，发送者优先使用群名片
+#[cfg(feature = "napcat-onebot")]
+fn onebot_message(msg: &Value) -> Option<HistoryMessage> {
+    let sender = &msg["sender"];
+    let nickname = sender["card"]
+        .as_str()
+        .filter(|v| !v.is_empty())
+        .or(sender["nickname"].as_str())
+        .unwrap_or_default();
+    Some(HistoryMessage {
+        user_id: sender["user_id"].as_i64().unwrap_or_default(),
+        nickname: nickname.to_string(),
+        time: msg["time"].as_i64()?,
+        text: segments_text(&msg["message"]),
+    })
+}
+
+/// 获取被引用的消息，合并转发的消息会展开其中的内容。按隐私设置过滤，引用的消息被过滤时返回 None
+#[cfg(feature = "napcat-onebot")]
+pub async fn fetch_quote(
+    bot: &RuntimeBot,
+    config: &HistoryConfig,
+    message_id: i64,
+) -> Result<Option<HistoryMessage>, String> {
+    let res = bot
+        .send_api_return("get_msg", json!({ "message_id": message_id }))
+        .await
+        .map_err(|err| err.to_string())?;
+    let msg = onebot_message(&res.data).ok_or("get_msg: invalid message")?;
+
+    let forward = res.data["message"]
+        .as_array()
+        .and_then(|v| v.iter().find(|segment| segment["type"] == "forward"));
+    // 展开失败时仍然提供引用的消息本身
+    let nodes = match forward {
+        Some(forward) => forward_nodes(bot, forward)
+            .await
+            .inspect_err(|err| log::warn!("aiqa: Failed to expand forward message: {}", err))
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    Ok(private_quote(msg, nodes, config))
+}
+
+/// 获取合并转发中的各条消息
+#[cfg(feature = "napcat-onebot")]
+async fn forward_nodes(bot: &RuntimeBot, forward: &Value) -> Result<Vec<HistoryMessage>, String> {
+    // NapCat 可能已经在消息段中附带了转发的内容
+    let nodes = match forward["data"]["content"].as_array() {
+        Some(nodes) => nodes.clone(),
+        None => {
+            let res = bot
+                .send_api_return(
+                    "get_forward_msg",
+                    json!({ "message_id": forward["data"]["id"] }),
+                )
+                .await
+                .map_err(|err| err.to_string())?;
+            res.data["messages"].as_array().cloned().unwrap_or_default()
+        }
+    };
+    Ok(nodes.iter().filter_map(onebot_message).collect())
+}
+
+/// 获取群内最近的 `count` 条消息，按时间从早到晚排列
+#[cfg(feature = "milky")]
+pub async fn fetch_group_history(
+    bot: &RuntimeBot,
+    group_id: i64,
+    count: usize,
+) -> Result<Vec<HistoryMessage>, String> {
+    // Milky 每次最多返回 30 条，需要分页向前获取
+    const PAGE: usize = 30;
+
+    let mut msgs: Vec<(i64, HistoryMessage)> = Vec::new();
+    let mut start: Option<i64> = None;
+
+    while msgs.len() < count {
+        let mut params = json!({
+            "message_scene": "group",
+            "peer_id": group_id,
+            "limit": PAGE.min(count - msgs.len()),
+        });
+        if let Some(seq) = start {
+            params["start_message_seq"] = json!(seq);
+        }
+
+        let res = bot
+            .send_api_return("get_history_messages", params)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let page = res.data["messages"]
+            .as_array()
+            .ok_or("get_history_messages: no messages")?;
+        if page.is_empty() {
+            break;
+        }
+
+        for msg in page {
+            let (Some(seq), Some(msg)) = (msg["message_seq"].as_i64(), milky_message(msg)) else {
+                continue;
+            };
+            msgs.push((seq, msg));
+        }
+
+        match res.data["next_message_seq"].as_i64() {
+            Some(seq) if Some(seq) != start => start = Some(seq),
+            _ => break,
+        }
+    }
+
+    msgs.sort_by_key(|(seq, _)| *seq);
+    msgs.dedup_by_key(|(seq, _)| *seq);
+    let skip = msgs.len().saturating_sub(count);
+    Ok(msgs.into_iter().skip(skip).map(|(_, msg)| msg).collect())
+}
+
+/// Milky 的消息，群聊中优先使用群名片，私聊中优先使用备注
+#[cfg(feature = "milky")]
+fn milky_message(msg: &Value) -> Option<HistoryMessage> {
+    let (member, friend) = (&msg["group_member"], &msg["friend"]);
+    let nickname = [
+        &member["card"],
+        &member["nickname"],
+        &friend["remark"],
+        &friend["nickname"],
+    ]
+    .into_iter()
+    .filter_map(|v| v.as_str())
+    .find(|v| !v.is_empty())
+    .unwrap_or_default();
+    Some(HistoryMessage {
+        user_id: msg["sender_id"].as_i64()?,
+        nickname: nickname.to_string(),
+        time: msg["time"].as_i64()?,
+        text: segments_text(&msg["segments"]),
+    })
+}
+
+/// 获取被引用的消息，合并转发的消息会展开其中的内容。按隐私设置过滤，引用的消息被过滤时返回 None
+#[cfg(feature = "milky")]
+pub async fn fetch_quote(
+    bot: &RuntimeBot,
+    config: &HistoryConfig,
+    message_scene: &str,
+    peer_id: i64,
+    message_seq: i64,
+) -> Result<Option<HistoryMessage>, String> {
+    let res = bot
+        .send_api_return(
+            "get_message",
+            json!({
+                "message_scene": message_scene,
+                "peer_id": peer_id,
+                "message_seq": message_seq,
+            }),
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+    let message = &res.data["message"];
+    let msg = milky_message(message).ok_or("get_message: invalid message")?;
+
+    let forward = message["segments"]
+        .as_array()
+        .and_then(|v| v.iter().find(|segment| segment["type"] == "forward"));
+    // 展开失败时仍然提供引用的消息本身
+    let nodes = match forward {
+        Some(forward) => forward_nodes(bot, forward)
+            .await
+            .inspect_err(|err| log::warn!("aiqa: Failed to expand forward message: {}", err))
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    Ok(private_quote(msg, nodes, config))
+}
+
+/// 获取合并转发中的各条消息
+#[cfg(feature = "milky")]
+async fn forward_nodes(bot: &RuntimeBot, forward: &Value) -> Result<Vec<HistoryMessage>, String> {
+    let res = bot
+        .send_api_return(
+            "get_forwarded_messages",
+            json!({ "forward_id": forward["data"]["forward_id"] }),
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+    // 转发的消息只有发送者名称，没有 QQ 号，无法按 `exclude_users` 排除
+    Ok(res.data["messages"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|node| {
+            Some(HistoryMessage {
+                user_id: 0,
+                nickname: node["sender_name"].as_str().unwrap_or_default().to_string(),
+                time: node["time"].as_i64()?,
+                text: segments_text(&node["segments"]),
+            })
+        })
+        .collect())
+}
+
+/// 展开合并转发时最多保留的消息数
+const FORWARD_MAX_MESSAGES: usize = 50;
+/// 展开合并转发时每条消息的最大字符数
+const FORWARD_MESSAGE_MAX_CHARS: usize = 300;
+
+/// 引用的消息与其中展开的合并转发同样按隐私设置过滤，引用的消息被过滤时返回 None
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn private_quote(
+    msg: HistoryMessage,
+    nodes: Vec<HistoryMessage>,
+    config: &HistoryConfig,
+) -> Option<HistoryMessage> {
+    let mut msg = apply_privacy(vec![msg], config, config.max_minutes).pop()?;
+    append_forward(&mut msg, &apply_privacy(nodes, config, config.max_minutes));
+    Some(msg)
+}
+
+/// 在消息后附上合并转发中的各条消息
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn append_forward(msg: &mut HistoryMessage, nodes: &[HistoryMessage]) {
+    let shown: Vec<HistoryMessage> = nodes
+        .iter()
+        .filter(|node| !node.text.is_empty())
+        .take(FORWARD_MAX_MESSAGES)
+        .map(|node| HistoryMessage {
+            text: crate::truncate_chars(&node.text, FORWARD_MESSAGE_MAX_CHARS),
+            ..node.clone()
+        })
+        .collect();
+    if shown.is_empty() {
+        return;
+    }
+    msg.text.push('\n');
+    msg.text.push_str(&format_messages(&shown));
+    let omitted = nodes.iter().filter(|node| !node.text.is_empty()).count() - shown.len();
+    if omitted > 0 {
+        msg.text
+            .push_str(&format!("\n（省略了 {} 条消息）", omitted));
+    }
+}
+
+/// 引用的消息附带给模型的形式，标明发送者与时间，内容放在 `<quote>` 标签中
+pub fn quote_prompt(msg: &HistoryMessage) -> String {
+    let nickname = match msg.nickname.is_empty() {
+        true => msg.user_id.to_string(),
+        false => msg.nickname.clone(),
+    };
+    let time = chrono::Local
+        .timestamp_opt(msg.time, 0)
+        .single()
+        .map(|v| v.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+    format!(
+        "[quoted from {} at {}]:\n{}",
+        nickname,
+        time,
+        crate::enclose("quote", &msg.text)
+    )
+}
+
+/// 把 OneBot 或 Milky 的消息段转换为文本，非文本内容用占位符表示
+pub fn segments_text(segments: &Value) -> String {
+    let Some(segments) = segments.as_array() else {
+        return String::new();
+    };
+
+    let mut text = String::new();
+    for segment in segments {
+        let data = &segment["data"];
+        match segment["type"].as_str().unwrap_or_default() {
+            "text" => text.push_str(data["text"].as_str().unwrap_or_default()),
+            "at" => match data["qq"].as_str() {
+                Some("all") => text.push_str("@全体成员"),
+                Some(qq) => text.push_str(&format!("@{}", qq)),
+                None => {}
+            },
+            "mention" => text.push_str(&format!("@{}", data["user_id"])),
+            "mention_all" => text.push_str("@全体成员"),
+            // NapCat 的 raw.faceText 形如 `/微笑`
+            "face" => match data["raw"]["faceText"].as_str() {
+                Some(name) if !name.is_empty() => {
+                    text.push_str(&format!("[表情:{}]", name.trim_start_matches('/')))
+                }
+                _ => text.push_str("[表情]"),
+            },
+            // 商城表情与动画表情的 summary 形如 `[滑稽]`
+            "mface" | "market_face" => {
+                text.push_str(bracketed(&data["summary"]).unwrap_or("[表情]"))
+            }
+            "image" => match bracketed(&data["summary"]) {
+                Some(summary) if summary != "[图片]" => text.push_str(summary),
+                _ => text.push_str("[图片]"),
+            },
+            "record" => text.push_str("[语音]"),
+            "video" => text.push_str("[视频]"),
+            "file" => {
+                let name = [&data["name"], &data["file_name"], &data["file"]]
+                    .into_iter()
+                    .filter_map(|v| v.as_str())
+                    .find(|v| !v.is_empty());
+                match name {
+                    Some(name) => text.push_str(&format!("[文件:{}]", name)),
+                    None => text.push_str("[文件]"),
+                }
+            }
+            "forward" => text.push_str("[聊天记录]"),
+            "reply" => {}
+            _ => text.push_str("[消息]"),
+        }
+    }
+    text.trim().to_string()
+}
+
+/// 以 `[` 开头、`]` 结尾的非空文本
+fn bracketed(value: &Value) -> Option<&str> {
+    value
+        .as_str()
+        .map(str::trim)
+        .filter(|v| v.len() > 2 && v.starts_with('[') && v.ends_with(']'))
+}
+
+/// 每条消息一行：`[月-日 时:分] 昵称: 内容`
+pub fn format_messages(msgs: &[HistoryMessage]) -> String {
+    msgs.iter()
+        .map(|msg| {
+            let time = chrono::Local
+                .timestamp_opt(msg.time, 0)
+                .single()
+                .map(|v| v.format("%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            format!("[{}] {}: {}", time, msg.nickname, msg.text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 按隐私设置过滤消息：去掉排除的用户、超出时间范围与空的消息，并截断过长的内容
+pub fn apply_privacy(
+    msgs: Vec<HistoryMessage>,
+    config: &HistoryConfig,
+    minutes: u64,
+) -> Vec<HistoryMessage> {
+    let now = chrono::Local::now().timestamp();
+    let since = if minutes > 0 {
+        now - minutes as i64 * 60
+    } else {
+        i64::MIN
+    };
+
+    msgs.into_iter()
+        .filter(|msg| !config.exclude_users.contains(&msg.user_id))
+        .filter(|msg| msg.time >= since)
+        .filter(|msg| !msg.text.is_empty())
+        .map(|mut msg| {
+            msg.text = crate::truncate_chars(&msg.text, config.message_max_chars);
+            msg
+        })
+        .collect()
+}
+
+/// 让模型查询当前群最近聊天记录的工具
+pub struct HistoryTool {
+    bot: Arc<RuntimeBot>,
+    group_id: i64,
+    config: HistoryConfig,
+}
+
+impl HistoryTool {
+    /// 群未开启历史查询时返回 None
+    pub fn new(bot: Arc<RuntimeBot>, group_id: i64, config: &HistoryConfig) -> Option<HistoryTool> {
+        if !config.allows(group_id) {
+            return None;
+        }
+        Some(HistoryTool {
+            bot,
+            group_id,
+            config: config.clone(),
+        })
+    }
+}
+
+impl Tool for HistoryTool {
+    fn name(&self) -> &str {
+        "get_group_history"
+    }
+
+    fn description(&self) -> &str {
+        "获取当前群聊最近的聊天记录，每行一条消息，格式为 `[月-日 时:分] 昵称: 内容`。需要了解群里讨论了什么时使用。"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "count": {
+                    "type": "integer",
+                    "description": format!("获取的消息条数，最多 {}", self.config.max_messages),
+                },
+                "minutes": {
+                    "type": "integer",
+                    "description": "只获取最近多少分钟内的消息，可省略",
+                },
+            },
+            "required": ["count"],
+        })
+    }
+
+    fn execute(&self, args: Value) -> BoxFuture<'_, Result<String, String>> {
+        Box::pin(async move {
+            let count = args["count"]
+                .as_u64()
+                .unwrap_or(20)
+                .clamp(1, self.config.max_messages as u64) as usize;
+            let minutes = match (args["minutes"].as_u64(), self.config.max_minutes) {
+                (Some(v), 0) => v,
+                (Some(v), max) => v.min(max),
+                (None, max) => max,
+            };
+
+            let msgs = fetch_group_history(&self.bot, self.group_id, count).await?;
+            let msgs = apply_privacy(msgs, &self.config, minutes);
+            if msgs.is_empty() {
+                return Ok("没有符合条件的消息".to_string());
+            }
+            Ok(format_messages(&msgs))
+        })
+    }
+}
+
+#[test]
+fn test_segments_text() {
+    let segments = json!([
+        {"type": "reply", "data": {"id": "1"}},
+        {"type": "at", "data": {"qq": "10001"}},
+        {"type": "text", "data": {"text": " 看看这个 "}},
+        {"type": "image", "data": {"file": "a.png"}},
+        {"type": "mention", "data": {"user_id": 10002}},
+    ]);
+    assert_eq!(segments_text(&segments), "@10001 看看这个 [图片]@10002");
+
+    let segments = json!([
+        {"type": "face", "data": {"id": "14", "raw": {"faceText": "/微笑"}}},
+        {"type": "face", "data": {"face_id": "14"}},
+        {"type": "image", "data": {"file": "a.gif", "summary": "[动画表情]"}},
+        {"type": "mface", "data": {"summary": "[滑稽]"}},
+        {"type": "file", "data": {"file": "abc", "name": "报告.pdf"}},
+        {"type": "file", "data": {"file_id": "abc", "file_name": "a.zip"}},
+        {"type": "forward", "data": {"id": "123"}},
+    ]);
+    assert_eq!(
+        segments_text(&segments),
+        "[表情:微笑][表情][动画表情][滑稽][文件:报告.pdf][文件:a.zip][聊天记录]"
+    );
+}
+
+#[test]
+fn test_quote_prompt() {
+    let time = chrono::Local
+        .with_ymd_and_hms(2024, 5, 1, 12, 30, 0)
+        .unwrap()
+        .timestamp();
+    let mut msg = HistoryMessage {
+        user_id: 10001,
+        nickname: "小明".to_string(),
+        time,
+        text: "[聊天记录]".to_string(),
+    };
+    let node = |nickname: &str, text: &str| HistoryMessage {
+        user_id: 0,
+        nickname: nickname.to_string(),
+        time,
+        text: text.to_string(),
+    };
+    append_forward(
+        &mut msg,
+        &[node("甲", "你好"), node("乙", ""), node("乙", "在吗")],
+    );
+    assert_eq!(
+        quote_prompt(&msg),
+        "[quoted from 小明 at 2024-05-01 12:30]:\n<quote>\n[聊天记录]\n[05-01 12:30] 甲: 你好\n[05-01 12:30] 乙: 在吗\n</quote>"
+    );
+
+    msg.nickname.clear();
+    msg.text = "hi".to_string();
+    assert!(quote_prompt(&msg).starts_with("[quoted from 10001 at"));
+}
+
+#[test]
+fn test_apply_privacy() {
+    let now = chrono::Local::now().timestamp();
+    let msg = |user_id, time, text: &str| HistoryMessage {
+        user_id,
+        nickname: format!("u{}", user_id),
+        time,
+        text: text.to_string(),
+    };
+    let config = HistoryConfig {
+        exclude_users: vec![2],
+        message_max_chars: 3,
+        ..HistoryConfig::default()
+    };
+
+    let msgs = apply_privacy(
+        vec![
+            msg(1, now - 3600, "old"),
+            msg(2, now, "hidden"),
+            msg(3, now, ""),
+            msg(1, now, "hello"),
+        ],
+        &config,
+        30,
+    );
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].text, "hel…");
+
+    // 引用的消息与展开的合并转发同样过滤
+    assert!(private_quote(msg(2, now, "hidden"), Vec::new(), &config).is_none());
+    assert!(private_quote(msg(1, now - 3600 * 48, "old"), Vec::new(), &config).is_none());
+    let quote = private_quote(
+        msg(1, now, "[聊天记录]"),
+        vec![msg(2, now, "hidden"), msg(3, now, "hello")],
+        &config,
+    )
+    .unwrap();
+    assert!(!quote.text.contains("hid"));
+    assert!(quote.text.ends_with("u3: hel…"));
+}
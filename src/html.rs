@@ -1,10 +1,21 @@
-pub static HTML_START_NEXT_IS_MD_CSS: &str = r#"<!doctype html>
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::error::TemplateError;
+
+/// 默认的页面模板，可以在插件数据目录下放置 `templates/page.html` 覆盖
+pub static DEFAULT_TEMPLATE: &str = r#"<!doctype html>
 <html>
 <head>
 <meta name="viewport" content="width=device-width, initial-scale=1">
-<style>"#;
+<style>{{css}}</style>
+</head>
+<body class="{{theme}}">
+<article class="markdown-body">{{header}}{{chat}}{{reasoning}}{{body}}{{footer}}</article>
+{{scripts}}
+</body></html>"#;
 
-pub static HTML_2_NEXT_IS_HIGHLIGHT_CSS: &str = r#"
+pub static BASE_CSS: &str = r#"
 .markdown-body {
     box-sizing: border-box;
     width: 100%;
@@ -16,26 +27,95 @@ pub static HTML_2_NEXT_IS_HIGHLIGHT_CSS: &str = r#"
     font-family: "MiSans", -apple-system, BlinkMacSystemFont, "Segoe UI", "Noto Sans", Helvetica, Arial, sans-serif, "Apple Color Emoji", "Segoe UI Emoji";
 }
 
+.aiqa-header {
+    display: flex;
+    align-items: center;
+    gap: 10px;
+    padding-bottom: 10px;
+    margin-bottom: 14px;
+    border-bottom: 1px solid rgba(127, 127, 127, 0.25);
+}
+
+.aiqa-avatar {
+    width: 36px;
+    height: 36px;
+    border-radius: 50%;
+    flex-shrink: 0;
+}
+
+.aiqa-nickname {
+    font-weight: 600;
+    font-size: 14px;
+}
+
+.aiqa-question {
+    font-size: 14px;
+    opacity: 0.75;
+    word-break: break-all;
+}
+
+.aiqa-chat {
+    display: flex;
+    align-items: flex-start;
+    gap: 10px;
+    margin-bottom: 16px;
+}
+
+.aiqa-bubble {
+    margin-top: 4px;
+    padding: 8px 12px;
+    border-radius: 4px 12px 12px 12px;
+    background-color: rgba(127, 127, 127, 0.12);
+    font-size: 14px;
+    white-space: pre-wrap;
+    word-break: break-all;
+}
+
+.aiqa-quote {
+    margin: 0 0 6px 0;
+    padding: 0 0 0 8px;
+    border-left: 3px solid rgba(127, 127, 127, 0.4);
+    opacity: 0.7;
+}
+
+.aiqa-reasoning {
+    margin-bottom: 14px;
+    padding: 4px 0 4px 10px;
+    border-left: 3px solid rgba(127, 127, 127, 0.4);
+    font-size: 13px;
+    opacity: 0.6;
+}
+
+.aiqa-reasoning-body {
+    margin-top: 6px;
+    white-space: pre-wrap;
+    word-break: break-all;
+}
+
+.aiqa-footer {
+    margin-top: 16px;
+    padding-top: 8px;
+    border-top: 1px solid rgba(127, 127, 127, 0.25);
+    font-size: 12px;
+    opacity: 0.6;
+}
+
 body{
     font-family: Arial, sans-serif; /* 选择无衬线字体 */
     margin: 0;
     padding: 0;
     overflow: hidden;
 }
-</style>
-<style>
-"#;
 
-pub static HTML_3_NEXT_IS_MD_BODY_AND_THEN_IS_HTML4: &str = r#"</style>
-</head>
-<body>
-<article class="markdown-body">"#;
-
-pub static HTML_4_NEXT_IS_HIGH_LIGHT_JS: &str = "</article><script>";
+@media print {
+    body {
+        overflow: visible;
+    }
+}
+"#;
 
-pub static HTML_END: &str = r#"</script><script>hljs.highlightAll();</script>
-<script>
-const elementsToCheck = ['pre', 'code']; // 需要检测的元素
+/// 页面加载完成后添加 `div.finish`，`ScreenshotManager` 以此判断可以截图
+pub static FINISH_JS: &str = r#"const elementsToCheck = ['pre', 'code']; // 需要检测的元素
 
 document.addEventListener("DOMContentLoaded", function() {
     const markdownBody = document.querySelector('.markdown-body');
@@ -60,16 +140,370 @@ document.addEventListener("DOMContentLoaded", function() {
     // 完成页面加载
     document.body.appendChild(finishedElement);
 });
-</script>
-</body></html>"#;
+"#;
+
+pub static SCRIPTS: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "<script>{}</script><script>hljs.highlightAll();</script><script>{}</script>",
+        HIGH_LIGHT_JS, FINISH_JS
+    )
+});
+
+/// 模板中可以使用的变量
+pub static VARIABLES: &[&str] = &[
+    "css",
+    "markdown_css",
+    "highlight_css",
+    "base_css",
+    "theme",
+    "body",
+    "header",
+    "chat",
+    "reasoning",
+    "footer",
+    "question",
+    "nickname",
+    "avatar",
+    "model",
+    "usage",
+    "latency",
+    "time",
+    "scripts",
+    "highlight_js",
+];
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var(String),
+    If(String, Vec<Node>),
+}
+
+/// 页面模板
+///
+/// 支持 `{{name}}` 变量替换与 `{{#if name}}...{{/if}}` 条件块，变量为空字符串时条件为假。
+/// 替换只进行一遍，变量值里面出现的 `{{...}}` 不会再被替换。
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    /// 读取数据目录下的 `templates/page.html`，不存在时使用默认模板
+    pub fn load(data_path: &Path) -> Result<Template, TemplateError> {
+        let file_path = data_path.join("templates").join("page.html");
+        if !file_path.exists() {
+            return Ok(Template::bundled());
+        }
+
+        let source = std::fs::read_to_string(&file_path)
+            .map_err(|err| TemplateError::ReadErr(err.to_string()))?;
+        let template = Template::parse(&source)?;
+        template.validate()?;
+
+        Ok(template)
+    }
+
+    pub fn bundled() -> Template {
+        Template::parse(DEFAULT_TEMPLATE).expect("bundled template is invalid")
+    }
+
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        // 栈底是根节点，每遇到一个 if 压入一层
+        let mut stack: Vec<(Option<String>, Vec<Node>)> = vec![(None, Vec::new())];
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .1
+                    .push(Node::Text(rest[..start].to_string()));
+            }
+            let after = &rest[start + 2..];
+            let end = after
+                .find("}}")
+                .ok_or_else(|| TemplateError::SyntaxErr("unclosed `{{`".to_string()))?;
+            let tag = after[..end].trim();
+
+            if let Some(name) = tag.strip_prefix("#if ") {
+                let name = check_variable(name.trim())?;
+                stack.push((Some(name), Vec::new()));
+            } else if tag == "/if" {
+                if stack.len() == 1 {
+                    return Err(TemplateError::SyntaxErr("unexpected `{{/if}}`".to_string()));
+                }
+                let (name, nodes) = stack.pop().unwrap();
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .1
+                    .push(Node::If(name.unwrap(), nodes));
+            } else {
+                let name = check_variable(tag)?;
+                stack.last_mut().unwrap().1.push(Node::Var(name));
+            }
+
+            rest = &after[end + 2..];
+        }
+
+        if !rest.is_empty() {
+            stack
+                .last_mut()
+                .unwrap()
+                .1
+                .push(Node::Text(rest.to_string()));
+        }
+
+        if stack.len() != 1 {
+            return Err(TemplateError::SyntaxErr("unclosed `{{#if}}`".to_string()));
+        }
+
+        Ok(Template {
+            nodes: stack.pop().unwrap().1,
+        })
+    }
+
+    /// 检查模板是否满足截图的约定：
+    /// `{{body}}` 在 `article.markdown-body` 内，且包含 `{{scripts}}` 以生成 `div.finish`。
+    /// 其他变量分别按全部有值、全部为空以及每个变量单独为空检查，
+    /// 条件块只在变量有值时输出，所以这样可以发现只在部分条件下成立的约定
+    pub fn validate(&self) -> Result<(), TemplateError> {
+        self.validate_with(|_| "x")?;
+        self.validate_with(|_| "")?;
+        for empty in VARIABLES {
+            self.validate_with(|name| if name == *empty { "" } else { "x" })
+                .map_err(|err| match err {
+                    TemplateError::ContractErr(msg) => {
+                        TemplateError::ContractErr(format!("{} (when `{}` is empty)", msg, empty))
+                    }
+                    err => err,
+                })?;
+        }
+        Ok(())
+    }
+
+    fn validate_with(&self, value: impl Fn(&str) -> &'static str) -> Result<(), TemplateError> {
+        const BODY: &str = "\u{0}aiqa-body\u{0}";
+        const SCRIPTS: &str = "\u{0}aiqa-scripts\u{0}";
+
+        let vars: Vec<(&str, &str)> = VARIABLES
+            .iter()
+            .map(|name| match *name {
+                "body" => (*name, BODY),
+                "scripts" => (*name, SCRIPTS),
+                _ => (*name, value(name)),
+            })
+            .collect();
+        let html = self.render(&vars);
+
+        if !html.contains(SCRIPTS) {
+            return Err(TemplateError::ContractErr(
+                "template must contain `{{scripts}}` outside conditionals".to_string(),
+            ));
+        }
+
+        let body = html.find(BODY).ok_or_else(|| {
+            TemplateError::ContractErr(
+                "template must contain `{{body}}` outside conditionals".to_string(),
+            )
+        })?;
+
+        let before = &html[..body];
+        let in_article = before.rfind("<article").is_some_and(|start| {
+            let tag_end = before[start..].find('>').map(|v| start + v);
+            tag_end.is_some_and(|tag_end| {
+                before[start..tag_end].contains("markdown-body")
+                    && !before[tag_end..].contains("</article")
+            })
+        });
+        if !in_article {
+            return Err(TemplateError::ContractErr(
+                "`{{body}}` must be inside `<article class=\"markdown-body\">`".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// 渲染模板，vars 中没有提供的变量视为空字符串
+    pub fn render(&self, vars: &[(&str, &str)]) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, vars, &mut out);
+        out
+    }
+}
+
+fn check_variable(name: &str) -> Result<String, TemplateError> {
+    if VARIABLES.contains(&name) {
+        Ok(name.to_string())
+    } else {
+        Err(TemplateError::UnknownVariable(name.to_string()))
+    }
+}
+
+fn render_nodes(nodes: &[Node], vars: &[(&str, &str)], out: &mut String) {
+    let get = |name: &str| {
+        vars.iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| *v)
+            .unwrap_or_default()
+    };
+
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(name) => out.push_str(get(name)),
+            Node::If(name, children) => {
+                if !get(name).is_empty() {
+                    render_nodes(children, vars, out);
+                }
+            }
+        }
+    }
+}
+
+pub static HIGH_LIGHT_JS: &str = include_str!("html/highlight.js");
+
+pub static HIGH_LIGHT_DARK_CSS: &str = include_str!("html/highlight_github_dark.css");
+
+pub static HIGH_LIGHT_LIGHT_CSS: &str = include_str!("html/highlight_github_light.css");
 
-pub static HIGH_LIGHT_JS_NEXT_IS_HTML_END: &str = include_str!("html/highlight.js");
+pub static GITHUB_MARKDOWN_LIGHT_CSS: &str = include_str!("html/github_md_light.css");
 
-pub static HIGH_LIGHT_DARK_CSS_NEXT_IS_HTML3: &str = include_str!("html/highlight_github_dark.css");
+pub static GITHUB_MARKDOWN_DARK_CSS: &str = include_str!("html/github_md_dark.css");
 
-pub static HIGH_LIGHT_LIGHT_CSS_NEXT_IS_HTML3: &str =
-    include_str!("html/highlight_github_light.css");
+/// 渲染在回答上下方的附加信息，为 None 的项不显示
+#[derive(Default)]
+pub struct PageMeta {
+    pub question: Option<String>,
+    pub nickname: Option<String>,
+    pub avatar: Option<String>,
+    pub model: Option<String>,
+    pub usage: Option<String>,
+    pub latency: Option<String>,
+    pub time: Option<String>,
+    pub chat: Option<ChatCard>,
+    pub reasoning: Option<ReasoningCard>,
+}
+
+/// 以聊天气泡的形式显示在回答上方的提问
+pub struct ChatCard {
+    pub nickname: String,
+    pub avatar: Option<String>,
+    pub question: String,
+    pub quote: Option<String>,
+}
+
+/// 显示在回答上方的思考过程
+pub struct ReasoningCard {
+    pub text: String,
+    /// 完整思考过程的字符数，`text` 可能已被截断
+    pub chars: usize,
+    /// 是否展开，不展开时图片中只显示标题
+    pub open: bool,
+}
+
+impl PageMeta {
+    pub fn header_html(&self) -> String {
+        if self.question.is_none() && self.nickname.is_none() && self.avatar.is_none() {
+            return String::new();
+        }
+
+        let mut html = String::from(r#"<header class="aiqa-header">"#);
+        if let Some(avatar) = &self.avatar {
+            html.push_str(&format!(
+                r#"<img class="aiqa-avatar" src="{}" onerror="this.remove()">"#,
+                escape(avatar)
+            ));
+        }
+        html.push_str("<div>");
+        if let Some(nickname) = &self.nickname {
+            html.push_str(&format!(
+                r#"<div class="aiqa-nickname">{}</div>"#,
+                escape(nickname)
+            ));
+        }
+        if let Some(question) = &self.question {
+            html.push_str(&format!(
+                r#"<div class="aiqa-question">{}</div>"#,
+                escape(question)
+            ));
+        }
+        html.push_str("</div></header>");
+        html
+    }
+
+    pub fn chat_html(&self) -> String {
+        let Some(chat) = &self.chat else {
+            return String::new();
+        };
+
+        let mut html = String::from(r#"<section class="aiqa-chat">"#);
+        if let Some(avatar) = &chat.avatar {
+            html.push_str(&format!(
+                r#"<img class="aiqa-avatar" src="{}" onerror="this.remove()">"#,
+                escape(avatar)
+            ));
+        }
+        html.push_str(&format!(
+            r#"<div><div class="aiqa-nickname">{}</div><div class="aiqa-bubble">"#,
+            escape(&chat.nickname)
+        ));
+        if let Some(quote) = &chat.quote {
+            html.push_str(&format!(
+                r#"<div class="aiqa-quote">{}</div>"#,
+                escape(quote)
+            ));
+        }
+        html.push_str(&escape(&chat.question));
+        html.push_str("</div></div></section>");
+        html
+    }
+
+    pub fn reasoning_html(&self) -> String {
+        let Some(reasoning) = &self.reasoning else {
+            return String::new();
+        };
+
+        format!(
+            r#"<details class="aiqa-reasoning"{}><summary>💭 已思考（{} 字）</summary><div class="aiqa-reasoning-body">{}</div></details>"#,
+            if reasoning.open { " open" } else { "" },
+            reasoning.chars,
+            escape(&reasoning.text)
+        )
+    }
+
+    pub fn footer_html(&self) -> String {
+        let items: Vec<String> = [&self.model, &self.usage, &self.latency, &self.time]
+            .into_iter()
+            .flatten()
+            .map(|v| escape(v))
+            .collect();
 
-pub static GITHUB_MARKDOWN_LIGHT_NEXT_IS_HTML2: &str = include_str!("html/github_md_light.css");
+        if items.is_empty() {
+            return String::new();
+        }
+
+        format!(
+            r#"<footer class="aiqa-footer">{}</footer>"#,
+            items.join(" · ")
+        )
+    }
+}
 
-pub static GITHUB_MARKDOWN_DARK_NEXT_IS_HTML2: &str = include_str!("html/github_md_dark.css");
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,408 @@
+//! 本地知识库：读取 `knowledge/` 下的 markdown 与文本文件，切分后建立 BM25 索引，
+//! 可选使用 embedding 进行混合检索
+//!
+//! `knowledge/` 下的文件所有群共用，`knowledge/<群号>/` 下的文件只在对应的群中使用。
+
+use crate::config::KnowledgeConfig;
+use crate::markdown;
+use crate::req::ChatClient;
+use kovi::log;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// BM25 参数
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// 每次请求 embedding 的片段数
+const EMBEDDING_BATCH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    /// 相对 `knowledge/` 的文件路径
+    pub source: String,
+    /// 所属的群，为 None 时所有群共用
+    pub group_id: Option<i64>,
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct KnowledgeBase {
+    chunks: Vec<Chunk>,
+    /// 每个片段的词频
+    terms: Vec<HashMap<String, u32>>,
+    lens: Vec<usize>,
+    avg_len: f64,
+    /// 包含某个词的片段数
+    df: HashMap<String, usize>,
+    embeddings: Vec<Vec<f32>>,
+    files: usize,
+}
+
+/// 已计算的 embedding，以片段内容为键保存在 `knowledge_embeddings.json`，重建索引时复用
+#[derive(Default, Serialize, Deserialize)]
+struct EmbeddingCache {
+    model: String,
+    items: HashMap<String, Vec<f32>>,
+}
+
+impl KnowledgeBase {
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    pub fn has_embeddings(&self) -> bool {
+        !self.embeddings.is_empty()
+    }
+
+    /// 是否有该群专属的片段
+    pub fn has_group(&self, group_id: i64) -> bool {
+        self.chunks
+            .iter()
+            .any(|chunk| chunk.group_id == Some(group_id))
+    }
+
+    /// 读取 `data_path/knowledge` 并建立索引，配置了 embedding 模型时同时计算 embedding
+    pub async fn build(
+        data_path: &Path,
+        config: &KnowledgeConfig,
+        client: &ChatClient,
+    ) -> Result<KnowledgeBase, String> {
+        let dir = data_path.join("knowledge");
+        let mut base = KnowledgeBase::default();
+        if !dir.exists() {
+            return Ok(base);
+        }
+
+        let mut files = Vec::new();
+        collect_files(&dir, &mut files).map_err(|err| err.to_string())?;
+        files.sort();
+
+        let mut chunks = Vec::new();
+        for path in files.iter() {
+            let text = match std::fs::read_to_string(path) {
+                Ok(v) => v,
+                Err(err) => {
+                    log::warn!("aiqa: Failed to read {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+            let relative = path.strip_prefix(&dir).unwrap_or(path);
+            // 子目录名为群号时只在该群使用，其余子目录视为共用
+            let group_id = if relative.components().count() > 1 {
+                relative
+                    .components()
+                    .next()
+                    .and_then(|v| v.as_os_str().to_str()?.parse().ok())
+            } else {
+                None
+            };
+            let source = relative.to_string_lossy().replace('\\', "/");
+            for text in markdown::split(&text, config.chunk_chars) {
+                let text = text.trim();
+                if text.is_empty() {
+                    continue;
+                }
+                chunks.push(Chunk {
+                    source: source.clone(),
+                    group_id,
+                    text: text.to_string(),
+                });
+            }
+        }
+
+        base.files = files.len();
+        base.index(chunks);
+
+        if let Some(model) = &config.embedding_model {
+            match embed_chunks(data_path, model, &base.chunks, client).await {
+                Ok(v) => base.embeddings = v,
+                // embedding 不可用时仍可使用关键词检索
+                Err(err) => log::warn!("aiqa: Failed to embed knowledge: {}", err),
+            }
+        }
+
+        Ok(base)
+    }
+
+    fn index(&mut self, chunks: Vec<Chunk>) {
+        self.terms = chunks
+            .iter()
+            .map(|chunk| {
+                let mut tf = HashMap::new();
+                for term in tokenize(&format!("{}\n{}", chunk.source, chunk.text)) {
+                    *tf.entry(term).or_insert(0) += 1;
+                }
+                tf
+            })
+            .collect();
+        self.lens = self
+            .terms
+            .iter()
+            .map(|tf| tf.values().sum::<u32>() as usize)
+            .collect();
+        self.avg_len = self.lens.iter().sum::<usize>() as f64 / self.lens.len().max(1) as f64;
+        self.df.clear();
+        for tf in self.terms.iter() {
+            for term in tf.keys() {
+                *self.df.entry(term.clone()).or_insert(0) += 1;
+            }
+        }
+        self.chunks = chunks;
+    }
+
+    fn bm25(&self, i: usize, query: &[String]) -> f64 {
+        let n = self.chunks.len() as f64;
+        let tf = &self.terms[i];
+        let len = self.lens[i] as f64;
+        query
+            .iter()
+            .filter_map(|term| {
+                let f = *tf.get(term)? as f64;
+                let df = *self.df.get(term)? as f64;
+                let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+                Some(idf * f * (K1 + 1.0) / (f + K1 * (1.0 - B + B * len / self.avg_len)))
+            })
+            .sum()
+    }
+
+    /// 检索与问题最相关的片段。`query_embedding` 为问题的 embedding，
+    /// 有 embedding 时按 `embedding_weight` 混合 BM25 与余弦相似度的得分
+    pub fn search(
+        &self,
+        query: &str,
+        query_embedding: Option<&[f32]>,
+        group_id: Option<i64>,
+        config: &KnowledgeConfig,
+    ) -> Vec<&Chunk> {
+        let terms: Vec<String> = {
+            let mut terms = tokenize(query);
+            terms.sort();
+            terms.dedup();
+            terms
+        };
+
+        let candidates: Vec<usize> = (0..self.chunks.len())
+            .filter(|i| {
+                let chunk_group = self.chunks[*i].group_id;
+                chunk_group.is_none() || chunk_group == group_id
+            })
+            .collect();
+
+        let bm25: Vec<f64> = candidates.iter().map(|i| self.bm25(*i, &terms)).collect();
+        let max_bm25 = bm25.iter().cloned().fold(0.0, f64::max);
+
+        let mut scored: Vec<(f64, usize)> = candidates
+            .iter()
+            .zip(bm25)
+            .filter_map(|(i, bm25)| {
+                let embedding = query_embedding.zip(self.embeddings.get(*i));
+                let score = match embedding {
+                    Some((a, b)) => {
+                        let w = config.embedding_weight;
+                        let bm25 = if max_bm25 > 0.0 { bm25 / max_bm25 } else { 0.0 };
+                        let cosine = cosine(a, b);
+                        if bm25 == 0.0 && cosine < config.min_similarity {
+                            return None;
+                        }
+                        (1.0 - w) * bm25 + w * cosine
+                    }
+                    None if bm25 > 0.0 => bm25,
+                    None => return None,
+                };
+                Some((score, *i))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+            .into_iter()
+            .take(config.top_k)
+            .map(|(_, i)| &self.chunks[i])
+            .collect()
+    }
+}
+
+/// 把检索到的片段整理为提示词，总长度不超过 `max_chars`
+pub fn prompt(chunks: &[&Chunk], max_chars: usize) -> Option<String> {
+    let mut text = String::from(
+        "以下是本群知识库中可能与问题相关的资料，回答时优先参考，资料与问题无关时忽略：\n",
+    );
+    let mut used = 0;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let len = chunk.text.chars().count();
+        if used > 0 && used + len > max_chars {
+            break;
+        }
+        text.push_str(&format!(
+            "\n[{}] 来源：{}\n{}\n",
+            i + 1,
+            chunk.source,
+            chunk.text
+        ));
+        used += len;
+    }
+    (used > 0).then_some(text)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path
+            .extension()
+            .and_then(|v| v.to_str())
+            .is_some_and(|ext| matches!(ext, "md" | "markdown" | "txt"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+async fn embed_chunks(
+    data_path: &Path,
+    model: &str,
+    chunks: &[Chunk],
+    client: &ChatClient,
+) -> Result<Vec<Vec<f32>>, String> {
+    let cache_path = data_path.join("knowledge_embeddings.json");
+    let mut cache: EmbeddingCache = std::fs::read_to_string(&cache_path)
+        .ok()
+        .and_then(|v| kovi::serde_json::from_str(&v).ok())
+        .filter(|v: &EmbeddingCache| v.model == model)
+        .unwrap_or_default();
+
+    let missing: Vec<String> = chunks
+        .iter()
+        .filter(|chunk| !cache.items.contains_key(&chunk.text))
+        .map(|chunk| chunk.text.clone())
+        .collect();
+    for batch in missing.chunks(EMBEDDING_BATCH) {
+        let embeddings = client
+            .embed(model, batch.to_vec())
+            .await
+            .map_err(|err| format!("embedding 请求失败: {}", err))?;
+        cache.items.extend(batch.iter().cloned().zip(embeddings));
+    }
+
+    let embeddings = chunks
+        .iter()
+        .map(|chunk| cache.items.get(&chunk.text).cloned().unwrap_or_default())
+        .collect();
+
+    // 只保留当前仍在使用的片段
+    cache.model = model.to_string();
+    cache
+        .items
+        .retain(|text, _| chunks.iter().any(|chunk| &chunk.text == text));
+    if let Ok(v) = kovi::serde_json::to_string(&cache)
+        && let Err(err) = std::fs::write(&cache_path, v)
+    {
+        log::warn!("aiqa: Failed to save embedding cache: {}", err);
+    }
+
+    Ok(embeddings)
+}
+
+pub(crate) fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let (mut dot, mut na, mut nb) = (0.0f64, 0.0f64, 0.0f64);
+    for (x, y) in a.iter().zip(b) {
+        let (x, y) = (*x as f64, *y as f64);
+        dot += x * y;
+        na += x * x;
+        nb += y * y;
+    }
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na.sqrt() * nb.sqrt())
+    }
+}
+
+/// 分词：英文与数字按词切分并转为小写，中文使用单字与相邻两字
+fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut word = String::new();
+    let mut prev_cjk: Option<char> = None;
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                terms.push(std::mem::take(&mut word));
+            }
+            terms.push(c.to_string());
+            if let Some(prev) = prev_cjk {
+                terms.push(format!("{}{}", prev, c));
+            }
+            prev_cjk = Some(c);
+            continue;
+        }
+        prev_cjk = None;
+        if c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            terms.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        terms.push(word);
+    }
+
+    terms
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x3040..=0x30FF | 0xAC00..=0xD7AF)
+}
+
+#[test]
+fn test_tokenize() {
+    assert_eq!(
+        tokenize("Rust 入门 v1.2"),
+        vec!["rust", "入", "门", "入门", "v1", "2"]
+    );
+}
+
+#[test]
+fn test_search() {
+    let chunk = |source: &str, group_id, text: &str| Chunk {
+        source: source.to_string(),
+        group_id,
+        text: text.to_string(),
+    };
+    let mut base = KnowledgeBase::default();
+    base.index(vec![
+        chunk("faq.md", None, "服务器地址是 mc.example.com，版本 1.20"),
+        chunk("faq.md", None, "入群请先阅读群公告"),
+        chunk("1/rules.md", Some(1), "本群禁止发广告"),
+        chunk("2/rules.md", Some(2), "本群可以发广告"),
+    ]);
+    let config = KnowledgeConfig::default();
+
+    let res = base.search("服务器地址是多少", None, Some(1), &config);
+    assert_eq!(res[0].text, "服务器地址是 mc.example.com，版本 1.20");
+
+    // 其他群的知识库不参与检索
+    let res = base.search("能发广告吗", None, Some(1), &config);
+    assert_eq!(res[0].source, "1/rules.md");
+    assert!(res.iter().all(|chunk| chunk.source != "2/rules.md"));
+
+    assert!(base.search("天气", None, Some(1), &config).is_empty());
+
+    assert!(base.has_group(1));
+    assert!(!base.has_group(3));
+}
@@ -1,13 +1,16 @@
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
-use config::Config;
+use config::{
+    CodeBlockMode, Config, FileFormat, HistoryConfig, IndicatorFallback, OutputMode,
+    ReasoningDisplay, TextFormat,
+};
 use kovi::chrono::{self, Timelike as _};
 use kovi::event::MessageEventTrait;
 use kovi::{Message, PluginBuilder as P, RuntimeBot, Segment as KoviSegment, log};
 use parking_lot::{Mutex, RwLock};
-use pulldown_cmark::Options;
 use std::path::PathBuf;
 use std::sync::{Arc, LazyLock};
+use std::time::Duration;
 
 #[cfg(not(any(feature = "napcat-onebot", feature = "milky")))]
 compile_error!("请至少启用一个协议 feature: \"napcat-onebot\" 或 \"milky\"");
@@ -24,34 +27,46 @@ use kovi_milky::*;
 #[cfg(any(feature = "napcat-onebot", feature = "milky"))]
 use crate::browser::ScreenshotManager;
 
+mod anthropic;
+mod breaker;
 mod browser;
+mod cache;
+mod calc;
 #[cfg(any(feature = "napcat-onebot", feature = "milky"))]
 mod config;
 mod error;
+mod fetch;
+mod flags;
+mod history;
 mod html;
+mod knowledge;
+mod markdown;
+#[cfg(test)]
+mod mock;
+mod ollama;
+mod openai;
+mod provider;
 mod req;
+mod search;
+mod summary;
+mod tools;
 
 static LIGHT: LazyLock<RwLock<bool>> = LazyLock::new(|| RwLock::new(true));
 
 #[kovi::plugin]
 async fn main() {
     let bot = P::get_runtime_bot();
-    let data_path = Arc::new(bot.get_data_path());
+    let data_path = bot.get_data_path();
 
-    let default_config = Config {
-        apikey: None,
-        base_url: None,
-        model_name: None,
-        cmd: '%',
-    };
+    let default_config = Config::default();
 
-    let (config, send_err_msg) = {
+    let (mut config, send_err_msg) = {
         let fallback = default_config.clone();
         match kovi::utils::load_json_data(default_config, data_path.join("config.json")) {
-            Ok(config) => (Arc::new(config), None),
+            Ok(config) => (config, None),
             Err(err) => {
                 log::error!("aiqa: Failed to load config: {}", err);
-                (Arc::new(fallback), Some("aiqa: Failed to load config"))
+                (fallback, Some("aiqa: Failed to load config"))
             }
         }
     };
@@ -64,7 +79,9 @@ async fn main() {
         .await;
     }
 
-    if config.apikey.is_none() || config.base_url.is_none() || config.model_name.is_none() {
+    // 本地的 Ollama 不需要 apikey
+    let apikey_missing = config.apikey.is_none() && config.provider != config::ProviderKind::Ollama;
+    if apikey_missing || config.base_url.is_none() || config.model_name.is_none() {
         log::error!("aiqa is not set");
         send_private_msg(
             &bot,
@@ -76,25 +93,97 @@ async fn main() {
         return;
     }
 
-    let screenshot = Arc::new(Mutex::new(ScreenshotManager::init().unwrap()));
-    let chat_client = Arc::new(req::ChatClient::new(&config));
+    for profile in config.profiles() {
+        if let Err(err) = config.generation.merge(&profile.generation).validate() {
+            log::warn!(
+                "aiqa: Invalid generation config for {}: {}",
+                profile.display_name(),
+                err
+            );
+        }
+    }
+
+    if config.mode_cmd == Some(config.cmd) {
+        log::warn!("aiqa: mode_cmd is the same as cmd and will never be used");
+    }
+
+    let template = match html::Template::load(&data_path) {
+        Ok(v) => v,
+        Err(err) => {
+            log::error!("aiqa: Failed to load template: {}", err);
+            send_private_msg(
+                &bot,
+                bot.get_main_admin().unwrap().try_as_i64_or_panic(),
+                &format!("aiqa: 自定义模板无效，已使用默认模板\n\n{}", err),
+            )
+            .await;
+            html::Template::bundled()
+        }
+    };
+
+    let chat_client = req::ChatClient::new(&config);
+
+    // 没有支持 embedding 的接口时关闭 embedding，不在每次提问时请求失败
+    if !chat_client.supports_embeddings() {
+        let mut disabled = Vec::new();
+        if config.knowledge.embedding_model.take().is_some() {
+            disabled.push("knowledge");
+        }
+        if config.cache.embedding_model.take().is_some() {
+            disabled.push("cache");
+        }
+        if !disabled.is_empty() {
+            log::error!(
+                "aiqa: No endpoint supports embeddings, disabled for {:?}",
+                disabled
+            );
+            send_private_msg(
+                &bot,
+                bot.get_main_admin().unwrap().try_as_i64_or_panic(),
+                &format!(
+                    "aiqa: 没有支持 embedding 的模型接口（需要 openai 或 ollama 格式），已关闭 {} 的 embedding_model",
+                    disabled.join("、")
+                ),
+            )
+            .await;
+        }
+    }
+
+    let knowledge = if config.knowledge.enabled {
+        match knowledge::KnowledgeBase::build(&data_path, &config.knowledge, &chat_client).await {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("aiqa: Failed to build knowledge base: {}", err);
+                knowledge::KnowledgeBase::default()
+            }
+        }
+    } else {
+        knowledge::KnowledgeBase::default()
+    };
+
+    let search = search::new(&config.search);
+    let fetcher = fetch::Fetcher::new(&config.fetch);
+
+    let ctx = Arc::new(Context {
+        bot,
+        screenshot: Arc::new(Mutex::new(ScreenshotManager::init().unwrap())),
+        chat_client,
+        data_path,
+        config,
+        template,
+        knowledge: RwLock::new(knowledge),
+        cache: Mutex::new(cache::AnswerCache::default()),
+        search,
+        fetcher,
+    });
 
     //检测时间，如果是白天就LIGHT为true
     let current_hour = chrono::Local::now().hour();
-    *LIGHT.write() = current_hour >= 6 && current_hour < 18;
-
-    P::on_msg(move |e| {
-        on_msg(
-            e,
-            bot.clone(),
-            screenshot.clone(),
-            chat_client.clone(),
-            data_path.clone(),
-            config.clone(),
-        )
-    });
+    *LIGHT.write() = (6..18).contains(&current_hour);
 
-    P::cron("0 6,18 * * *", || cron()).unwrap();
+    P::on_msg(move |e| on_msg(e, ctx.clone()));
+
+    P::cron("0 6,18 * * *", cron).unwrap();
 
     async fn cron() {
         let mut light = LIGHT.write();
@@ -103,58 +192,299 @@ async fn main() {
 }
 
 #[cfg(any(feature = "napcat-onebot", feature = "milky"))]
-async fn on_msg(
-    e: Arc<MsgEvent>,
+struct Context {
     bot: Arc<RuntimeBot>,
     screenshot: Arc<Mutex<ScreenshotManager>>,
-    chat_client: Arc<req::ChatClient>,
-    data_path: Arc<PathBuf>,
-    config: Arc<Config>,
-) {
+    chat_client: req::ChatClient,
+    data_path: PathBuf,
+    config: Config,
+    template: html::Template,
+    knowledge: RwLock<knowledge::KnowledgeBase>,
+    cache: Mutex<cache::AnswerCache>,
+    search: Option<Arc<dyn search::SearchProvider>>,
+    fetcher: Option<fetch::Fetcher>,
+}
+
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn on_msg(e: Arc<MsgEvent>, ctx: Arc<Context>) {
     let text = match e.borrow_text() {
         Some(v) => v,
         None => return,
     };
 
-    if text.starts_with(&format!("{}{}", config.cmd, config.cmd)) {
-        send_emoji_msg(&e, &bot, true).await;
-        send_text(&e, &bot, &chat_client, &config).await;
-        send_emoji_msg(&e, &bot, false).await;
-    } else if text.starts_with(config.cmd) {
-        send_emoji_msg(&e, &bot, true).await;
-        send_img(&e, &bot, &screenshot, &chat_client, &data_path, &config).await;
-        send_emoji_msg(&e, &bot, false).await;
+    let Some((mode, rest)) = parse_prefix(text, &ctx.config) else {
+        return;
+    };
+
+    indicate(&e, &ctx, Indicator::Processing).await;
+    let success = match rest.trim() {
+        "reindex" => reindex(&e, &ctx).await,
+        "status" => status(&e, &ctx),
+        "models" => models(&e, &ctx).await,
+        _ => match summary::parse_command(rest) {
+            Some(count) => summary::run(&e, &ctx, count, mode).await,
+            None => answer(&e, &ctx, mode).await,
+        },
+    };
+    let done = match success {
+        true => Indicator::Success,
+        false => Indicator::Failure,
+    };
+    indicate(&e, &ctx, done).await;
+}
+
+/// `%reindex` 重新读取知识库，仅管理员可用
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn reindex(e: &MsgEvent, ctx: &Context) -> bool {
+    if !is_admin(e, &ctx.bot) {
+        e.reply_and_quote("只有管理员可以重建知识库");
+        return false;
+    }
+    if !ctx.config.knowledge.enabled {
+        e.reply_and_quote("知识库没有开启");
+        return false;
+    }
+
+    match knowledge::KnowledgeBase::build(&ctx.data_path, &ctx.config.knowledge, &ctx.chat_client)
+        .await
+    {
+        Ok(base) => {
+            let mut msg = format!(
+                "知识库已重建：{} 个文件，{} 个片段",
+                base.files(),
+                base.len()
+            );
+            if ctx.config.knowledge.embedding_model.is_some()
+                && !base.is_empty()
+                && !base.has_embeddings()
+            {
+                msg.push_str("\n\nembedding 请求失败，暂时只使用关键词检索");
+            }
+            *ctx.knowledge.write() = base;
+            e.reply_and_quote(msg);
+            true
+        }
+        Err(err) => {
+            log::error!("aiqa: Failed to build knowledge base: {}", err);
+            e.reply_and_quote(format!("知识库重建失败\n\n{}", err));
+            false
+        }
     }
 }
 
+/// `%status` 查看各模型接口的状态，仅管理员可用
 #[cfg(any(feature = "napcat-onebot", feature = "milky"))]
-async fn send_img(
-    e: &MsgEvent,
-    bot: &RuntimeBot,
-    screenshot: &Mutex<ScreenshotManager>,
-    chat_client: &req::ChatClient,
-    data_path: &PathBuf,
-    config: &Config,
-) {
-    let res = gpt_request(e, bot, chat_client, config).await;
+fn status(e: &MsgEvent, ctx: &Context) -> bool {
+    if !is_admin(e, &ctx.bot) {
+        e.reply_and_quote("只有管理员可以查看状态");
+        return false;
+    }
+    e.reply_and_quote(ctx.chat_client.status());
+    true
+}
+
+/// `%models` 列出各模型接口提供的模型，仅管理员可用
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn models(e: &MsgEvent, ctx: &Context) -> bool {
+    if !is_admin(e, &ctx.bot) {
+        e.reply_and_quote("只有管理员可以查看模型列表");
+        return false;
+    }
+
+    let mut lines = Vec::new();
+    for (name, res) in ctx.chat_client.list_models().await {
+        match res {
+            Ok(models) if models.is_empty() => lines.push(format!("{}：没有模型", name)),
+            Ok(models) => {
+                lines.push(format!("{}：", name));
+                lines.extend(models.into_iter().map(|model| match model.detail {
+                    Some(detail) => format!("- {}（{}）", model.name, detail),
+                    None => format!("- {}", model.name),
+                }));
+            }
+            Err(err) => lines.push(format!("{}：获取失败，{}", name, err)),
+        }
+    }
+    e.reply_and_quote(lines.join("\n"));
+    true
+}
+
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn is_admin(e: &MsgEvent, bot: &RuntimeBot) -> bool {
+    let Some(sender) = e.get_sender_id().try_as_i64().copied() else {
+        return false;
+    };
+    bot.get_all_admin()
+        .unwrap_or_default()
+        .iter()
+        .any(|id| id.try_as_i64() == Some(sender))
+}
 
-    let res = match res {
+/// 解析命令前缀，返回输出方式与去掉前缀后的文本
+///
+/// `%` 使用图片，`%%` 使用文本，配置了 `mode_cmd` 时以它开头的问题使用 `default_mode`
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn parse_prefix<'a>(text: &'a str, config: &Config) -> Option<(OutputMode, &'a str)> {
+    if let Some(rest) = text.strip_prefix(config.cmd) {
+        return match rest.strip_prefix(config.cmd) {
+            Some(rest) => Some((OutputMode::Text, rest)),
+            None => Some((OutputMode::Image, rest)),
+        };
+    }
+    let rest = text.strip_prefix(config.mode_cmd?)?;
+    Some((config.default_mode, rest))
+}
+
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn answer(e: &MsgEvent, ctx: &Context, mode: OutputMode) -> bool {
+    let flags = match question_flags(e, &ctx.config) {
         Ok(v) => v,
         Err(err) => {
-            e.reply_and_quote(format!("你的问题太难了，我不会Q-Q。\n\n{}", err));
-            return;
+            e.reply_and_quote(format!("{}\n\n{}", err, flags::HELP));
+            return false;
         }
     };
 
-    let html = md_to_html(&res);
+    let quoted = get_quote(
+        &ctx.bot,
+        &ctx.config.history,
+        e,
+        e.get_message().get("reply"),
+    )
+    .await;
+    let quote = quoted.as_ref().map(|msg| msg.text.clone());
+
+    // 缓存的范围与问题的归一化形式
+    let cache_ref = cache_ref(e, ctx, quote.as_deref(), &flags);
+    let lookup = |embedding: Option<&[f32]>| {
+        cache_ref.as_ref().and_then(|(scope, key)| {
+            ctx.cache
+                .lock()
+                .get(scope, key, embedding, &ctx.config.cache)
+        })
+    };
+    // 先查相同的问题，没有命中时才计算 embedding 查相似的问题
+    let mut hit = lookup(None);
+    let embedding = match (&cache_ref, &ctx.config.cache.embedding_model) {
+        (Some(_), Some(model)) if hit.is_none() => ctx
+            .chat_client
+            .embed(model, vec![query_text(e, ctx, quote.as_deref())])
+            .await
+            .ok()
+            .and_then(|mut v| v.pop()),
+        _ => None,
+    };
+    if embedding.is_some() {
+        hit = lookup(embedding.as_deref());
+    }
+
+    let (res, cached_png, cache_key) = match hit {
+        Some(hit) => {
+            log::info!("aiqa: answer cache hit");
+            (hit.completion, hit.png, Some(hit.key))
+        }
+        None => {
+            let res = match gpt_request(e, quoted.as_ref(), ctx, &flags.generation).await {
+                Ok(v) => v,
+                Err(err) => {
+                    e.reply_and_quote(format!("你的问题太难了，我不会Q-Q。\n\n{}", err));
+                    return false;
+                }
+            };
+            // 调用过工具的回答可能依赖当时的状态，不缓存
+            let key = match &cache_ref {
+                Some((scope, key)) if !res.used_tools => {
+                    ctx.cache
+                        .lock()
+                        .insert(scope, key.clone(), embedding, &res, &ctx.config.cache);
+                    Some(key.clone())
+                }
+                _ => None,
+            };
+            (res, None, key)
+        }
+    };
 
-    if !data_path.exists() {
-        std::fs::create_dir_all(data_path).unwrap();
+    let Some(png) = send_answer(e, ctx, mode, quote, &res, cached_png).await else {
+        return false;
+    };
+    if let (Some((scope, _)), Some(key), Some((light, png))) = (&cache_ref, &cache_key, png) {
+        ctx.cache
+            .lock()
+            .set_png(scope, key, light, png, &ctx.config.cache);
+    }
+    true
+}
+
+/// 按输出方式发送回答，`Auto` 时根据回答内容选择。`cached_png` 为缓存的图片与渲染时是否为浅色主题，
+/// 主题相同时直接发送。失败时返回 None，成功时返回图片模式下发送的图片，用于缓存
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn send_answer(
+    e: &MsgEvent,
+    ctx: &Context,
+    mode: OutputMode,
+    quote: Option<String>,
+    res: &req::Completion,
+    cached_png: Option<(bool, Vec<u8>)>,
+) -> Option<Option<(bool, Vec<u8>)>> {
+    let mode = match mode {
+        OutputMode::Auto => {
+            let features = markdown::analyze(&res.content);
+            if ctx.config.file.threshold > 0 && features.chars > ctx.config.file.threshold {
+                OutputMode::File
+            } else if ctx.config.auto.prefers_image(&features) {
+                OutputMode::Image
+            } else {
+                OutputMode::Text
+            }
+        }
+        mode => mode,
+    };
+
+    if ctx.config.reasoning.display == ReasoningDisplay::Forward
+        && let Some(reasoning) = &res.reasoning
+    {
+        send_reasoning(e, ctx, reasoning).await;
     }
 
-    let file_path = data_path.join("output.html");
+    match mode {
+        OutputMode::Text => {
+            send_text(e, ctx, res).await;
+            Some(None)
+        }
+        OutputMode::File => send_file(e, ctx, quote, res).await.then_some(None),
+        _ => {
+            let light = *LIGHT.read();
+            let png = match cached_png {
+                Some((cached_light, png)) if cached_light == light => {
+                    send_png(e, &png);
+                    png
+                }
+                _ => send_img(e, ctx, quote, res)?,
+            };
+            send_code_blocks(e, ctx, &res.content).await;
+            Some(Some((light, png)))
+        }
+    }
+}
+
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn send_img(
+    e: &MsgEvent,
+    ctx: &Context,
+    quote: Option<String>,
+    res: &req::Completion,
+) -> Option<Vec<u8>> {
+    let meta = page_meta(e, &ctx.config, quote, res);
+    let html = md_to_html(&res.content, &meta, &ctx.template);
+
+    if !ctx.data_path.exists() {
+        std::fs::create_dir_all(&ctx.data_path).unwrap();
+    }
 
-    let mut screenshot_lock = screenshot.lock();
+    let file_path = ctx.data_path.join("output.html");
+
+    let mut screenshot_lock = ctx.screenshot.lock();
 
     std::fs::write(&file_path, &html).unwrap();
 
@@ -163,34 +493,223 @@ async fn send_img(
         Err(err) => {
             log::error!("{}", err);
             e.reply_and_quote(format!("你的问题太难了，我不会Q-Q。\n\n{}", err));
-            return;
+            return None;
         }
     };
 
-    let base64_img = image_to_base64(png_data);
+    send_png(e, &png_data);
+
+    Some(png_data)
+}
+
+fn send_png(e: &MsgEvent, png: &[u8]) {
+    let base64_img = image_to_base64(png.to_vec());
 
     let msg = Message::new().add_image(&format!("base64://{}", base64_img));
 
     e.reply_and_quote(msg);
 }
 
+/// 将完整回答渲染为 HTML 或 PDF 文件上传，聊天中只发送开头的摘要。生成文件失败时返回 false
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn send_file(
+    e: &MsgEvent,
+    ctx: &Context,
+    quote: Option<String>,
+    res: &req::Completion,
+) -> bool {
+    let file_config = &ctx.config.file;
+    let meta = page_meta(e, &ctx.config, quote, res);
+    let html = md_to_html(&res.content, &meta, &ctx.template);
+    let name = format!("aiqa_{}", chrono::Local::now().format("%Y%m%d_%H%M%S"));
+
+    let (data, name) = match file_config.format {
+        FileFormat::Html => (html.into_bytes(), format!("{}.html", name)),
+        FileFormat::Pdf => {
+            if !ctx.data_path.exists() {
+                std::fs::create_dir_all(&ctx.data_path).unwrap();
+            }
+
+            let file_path = ctx.data_path.join("output.html");
+
+            let pdf_data = {
+                let mut screenshot_lock = ctx.screenshot.lock();
+                std::fs::write(&file_path, &html).unwrap();
+                screenshot_lock.print_pdf(&file_path)
+            };
+
+            match pdf_data {
+                Ok(v) => (v, format!("{}.pdf", name)),
+                Err(err) => {
+                    log::error!("{}", err);
+                    e.reply_and_quote(format!("你的问题太难了，我不会Q-Q。\n\n{}", err));
+                    return false;
+                }
+            }
+        }
+    };
+
+    let file = format!("base64://{}", STANDARD.encode(&data));
+    if let Err(err) = upload_file(e, &ctx.bot, &file, &name).await {
+        log::error!("aiqa: Failed to upload {}, fallback to text: {}", name, err);
+        send_text(e, ctx, res).await;
+        return true;
+    }
+
+    let summary = truncate_chars(
+        &markdown::first_paragraph(&res.content),
+        file_config.summary_chars,
+    );
+    e.reply_and_quote(format!("{}\n\n完整回答见文件 {}", summary, name));
+    true
+}
+
+/// 图片中的代码无法复制，按配置把代码块另外发送
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn send_code_blocks(e: &MsgEvent, ctx: &Context, md: &str) {
+    let mode = ctx.config.code_blocks(group_id(e));
+    if mode == CodeBlockMode::Off {
+        return;
+    }
+
+    for (i, block) in markdown::code_blocks(md).into_iter().enumerate() {
+        if mode == CodeBlockMode::File {
+            let name = format!("code_{}.{}", i + 1, block.extension());
+            let file = format!("base64://{}", STANDARD.encode(block.code.as_bytes()));
+            match upload_file(e, &ctx.bot, &file, &name).await {
+                Ok(()) => continue,
+                Err(err) => {
+                    log::warn!("aiqa: Failed to upload {}, fallback to text: {}", name, err)
+                }
+            }
+        }
+        e.reply(block.code.trim_end().to_string());
+    }
+}
+
+/// 处理消息的阶段，在原消息上显示对应的表情回应
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Indicator {
+    Processing,
+    Success,
+    Failure,
+}
+
 #[cfg(feature = "napcat-onebot")]
-async fn send_emoji_msg(e: &MsgEvent, bot: &RuntimeBot, _is_add: bool) {
-    let _ =
-        kovi_plugin_expand_napcat::NapCatApi::set_msg_emoji_like(bot, e.message_id.into(), "424")
-            .await;
+async fn indicate(e: &MsgEvent, ctx: &Context, state: Indicator) {
+    let config = &ctx.config.indicator;
+    if !config.enabled {
+        return;
+    }
+
+    // NapCat 只能在群聊中回应表情
+    if e.group_id.is_none() {
+        if state == Indicator::Processing {
+            match config.fallback {
+                IndicatorFallback::Typing => {
+                    let _ = ctx
+                        .bot
+                        .send_api_return(
+                            "set_input_status",
+                            kovi::serde_json::json!({ "user_id": e.user_id, "event_type": 1 }),
+                        )
+                        .await;
+                }
+                IndicatorFallback::Text => e.reply(config.text.as_str()),
+                IndicatorFallback::None => {}
+            }
+        }
+        return;
+    }
+
+    match state {
+        Indicator::Processing => set_emoji(e, &ctx.bot, &config.processing, true).await,
+        Indicator::Success => {
+            set_emoji(e, &ctx.bot, &config.processing, false).await;
+            set_emoji(e, &ctx.bot, &config.success, true).await;
+        }
+        Indicator::Failure => {
+            set_emoji(e, &ctx.bot, &config.processing, false).await;
+            set_emoji(e, &ctx.bot, &config.failure, true).await;
+        }
+    }
+}
+
+/// 添加或移除表情回应，`emoji_id` 为空时不操作
+#[cfg(feature = "napcat-onebot")]
+async fn set_emoji(e: &MsgEvent, bot: &RuntimeBot, emoji_id: &str, set: bool) {
+    if emoji_id.is_empty() {
+        return;
+    }
+    let params = kovi::serde_json::json!({
+        "message_id": e.message_id,
+        "emoji_id": emoji_id,
+        "set": set,
+    });
+    if let Err(err) = bot.send_api_return("set_msg_emoji_like", params).await {
+        log::debug!("aiqa: Failed to set emoji like: {:?}", err);
+    }
 }
 
 #[cfg(feature = "milky")]
-async fn send_emoji_msg(e: &MsgEvent, bot: &RuntimeBot, is_add: bool) {
+async fn indicate(e: &MsgEvent, ctx: &Context, state: Indicator) {
     use kovi_milky::MilkyGroupApi;
 
-    let group_id = match e.data.group.as_ref() {
-        Some(group) => group.group_id,
-        None => return,
+    let config = &ctx.config.indicator;
+    if !config.enabled {
+        return;
+    }
+    let Some((scene, peer_id)) = message_scene(e) else {
+        return;
     };
 
-    bot.send_group_message_reaction(group_id, e.data.message_seq, "424", "face", is_add);
+    // Milky 只能在群聊中回应表情
+    if scene != "group" {
+        if state == Indicator::Processing {
+            // Milky 没有“对方正在输入”的接口，只在明确配置为 `text` 时发送文本
+            if config.fallback == IndicatorFallback::Text {
+                e.reply(config.text.as_str());
+            }
+        }
+        return;
+    }
+
+    let set_emoji = |emoji_id: &str, is_add: bool| {
+        if emoji_id.is_empty() {
+            return;
+        }
+        // QQ 表情的编号小于 10000，更大的是 emoji 的码位
+        let kind = match emoji_id.parse::<u32>() {
+            Ok(v) if v >= 10000 => "emoji",
+            _ => "face",
+        };
+        ctx.bot
+            .send_group_message_reaction(peer_id, e.data.message_seq, emoji_id, kind, is_add);
+    };
+    match state {
+        Indicator::Processing => set_emoji(&config.processing, true),
+        Indicator::Success => {
+            set_emoji(&config.processing, false);
+            set_emoji(&config.success, true);
+        }
+        Indicator::Failure => {
+            set_emoji(&config.processing, false);
+            set_emoji(&config.failure, true);
+        }
+    }
+}
+
+/// Milky 消息的场景（`friend`、`group` 或 `temp`）与会话 id：群聊为群号，私聊与临时会话为对方的 QQ 号
+#[cfg(feature = "milky")]
+fn message_scene(e: &MsgEvent) -> Option<(&str, i64)> {
+    let scene = e.get_message_type_str()?;
+    let peer_id = match scene {
+        "group" => e.data.group.as_ref()?.group_id,
+        "friend" | "temp" => *e.get_sender_id().try_as_i64()?,
+        _ => return None,
+    };
+    Some((scene, peer_id))
 }
 
 #[cfg(feature = "napcat-onebot")]
@@ -206,119 +725,546 @@ async fn send_private_msg(bot: &RuntimeBot, user_id: i64, text: &str) {
 }
 
 #[cfg(any(feature = "napcat-onebot", feature = "milky"))]
-async fn send_text(e: &MsgEvent, bot: &RuntimeBot, chat_client: &req::ChatClient, config: &Config) {
-    let res = gpt_request(e, bot, chat_client, config).await;
+async fn send_text(e: &MsgEvent, ctx: &Context, res: &req::Completion) {
+    let text_config = &ctx.config.text;
+    let format = |md: &str| match ctx.config.text_format(group_id(e)) {
+        TextFormat::Raw => md.to_string(),
+        TextFormat::Plain => markdown::to_plain_text(md),
+    };
+
+    if text_config.forward_threshold == 0
+        || res.content.chars().count() <= text_config.forward_threshold
+    {
+        e.reply_and_quote(format(&res.content));
+        return;
+    }
 
-    match res {
-        Ok(v) => {
-            e.reply_and_quote(v);
+    let chunks: Vec<String> = markdown::split(&res.content, text_config.chunk_chars)
+        .iter()
+        .map(|chunk| format(chunk))
+        .collect();
+
+    if let Err(err) = send_forward_msg(e, &ctx.bot, &text_config.forward_name, &chunks).await {
+        log::warn!(
+            "aiqa: Failed to send forward msg, fallback to sequential: {}",
+            err
+        );
+
+        let mut chunks = chunks.into_iter();
+        if let Some(first) = chunks.next() {
+            e.reply_and_quote(first);
         }
-        Err(err) => {
-            e.reply_and_quote(format!("你的问题太难了，我不会Q-Q。\n\n{}", err));
+        for chunk in chunks {
+            e.reply(chunk);
         }
-    };
+    }
 }
 
+/// 以合并转发消息发送思考过程，失败时不再发送
 #[cfg(any(feature = "napcat-onebot", feature = "milky"))]
-async fn gpt_request(
+async fn send_reasoning(e: &MsgEvent, ctx: &Context, reasoning: &str) {
+    let mut chunks = vec!["💭 思考过程".to_string()];
+    chunks.extend(markdown::split(reasoning, ctx.config.text.chunk_chars));
+
+    if let Err(err) = send_forward_msg(e, &ctx.bot, &ctx.config.text.forward_name, &chunks).await {
+        log::warn!("aiqa: Failed to send reasoning: {}", err);
+    }
+}
+
+#[cfg(feature = "napcat-onebot")]
+async fn upload_file(e: &MsgEvent, bot: &RuntimeBot, file: &str, name: &str) -> Result<(), String> {
+    use kovi_plugin_expand_napcat::NapCatApi;
+
+    let res = match e.group_id {
+        Some(group_id) => bot.upload_group_file(group_id, file, name, None).await,
+        None => bot.upload_private_file(e.user_id, file, name).await,
+    };
+
+    res.map(|_| ()).map_err(|err| err.to_string())
+}
+
+#[cfg(feature = "milky")]
+async fn upload_file(e: &MsgEvent, bot: &RuntimeBot, file: &str, name: &str) -> Result<(), String> {
+    let (scene, peer_id) = message_scene(e).ok_or("无法确定消息所在的会话")?;
+    let res = match scene {
+        "group" => {
+            bot.send_api_return(
+                "upload_group_file",
+                kovi::serde_json::json!({
+                    "group_id": peer_id,
+                    "parent_folder_id": "/",
+                    "file_uri": file,
+                    "file_name": name,
+                }),
+            )
+            .await
+        }
+        "friend" => {
+            bot.send_api_return(
+                "upload_private_file",
+                kovi::serde_json::json!({
+                    "user_id": peer_id,
+                    "file_uri": file,
+                    "file_name": name,
+                }),
+            )
+            .await
+        }
+        // 临时会话没有上传文件的接口
+        _ => return Err(format!("{} 会话不支持上传文件", scene)),
+    };
+
+    res.map(|_| ()).map_err(|err| err.to_string())
+}
+
+#[cfg(feature = "napcat-onebot")]
+async fn send_forward_msg(
     e: &MsgEvent,
     bot: &RuntimeBot,
-    chat_client: &req::ChatClient,
-    config: &Config,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let text = e.borrow_text().unwrap();
+    name: &str,
+    texts: &[String],
+) -> Result<(), String> {
+    use kovi_plugin_expand_napcat::{NapCatApi, Node};
+
+    let nodes: Vec<Node> = texts
+        .iter()
+        .map(|text| {
+            Node::new(
+                "node",
+                kovi::serde_json::json!({
+                    "user_id": e.self_id.to_string(),
+                    "nickname": name,
+                    "content": [{ "type": "text", "data": { "text": text } }],
+                }),
+            )
+        })
+        .collect();
+
+    let res = match e.group_id {
+        Some(group_id) => bot.send_group_forward_msg(group_id, nodes).await,
+        None => bot.send_private_forward_msg(e.user_id, nodes).await,
+    };
 
-    let quote = get_guote_text(bot, e, e.get_message().get("reply")).await;
+    res.map(|_| ()).map_err(|err| err.to_string())
+}
 
-    let text = text.trim_matches(config.cmd).trim();
+#[cfg(feature = "milky")]
+async fn send_forward_msg(
+    e: &MsgEvent,
+    bot: &RuntimeBot,
+    name: &str,
+    texts: &[String],
+) -> Result<(), String> {
+    use kovi_milky::MilkyMessageApi;
+
+    let messages: Vec<_> = texts
+        .iter()
+        .map(|text| {
+            kovi::serde_json::json!({
+                "user_id": e.self_id,
+                "sender_name": name,
+                "segments": [{ "type": "text", "data": { "text": text } }],
+            })
+        })
+        .collect();
+
+    let mut msg = Message::new();
+    msg.push(KoviSegment::new(
+        "forward",
+        kovi::serde_json::json!({ "messages": messages }),
+    ));
+
+    let (scene, peer_id) = message_scene(e).ok_or("无法确定消息所在的会话")?;
+    let res = match scene {
+        "group" => bot.send_group_message(peer_id, msg).await,
+        "friend" => bot.send_private_message(peer_id, msg).await,
+        // 临时会话不能通过好友消息接口发送
+        _ => return Err(format!("{} 会话不支持合并转发", scene)),
+    };
+
+    res.map(|_| ()).map_err(|err| err.to_string())
+}
+
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn gpt_request(
+    e: &MsgEvent,
+    quote: Option<&history::HistoryMessage>,
+    ctx: &Context,
+    params: &config::GenerationConfig,
+) -> Result<req::Completion, Box<dyn std::error::Error>> {
+    let text = question_text(e, &ctx.config);
 
     let mut vec: Vec<req::Message> = Vec::new();
 
+    let quote_text = quote.map(|msg| msg.text.as_str());
+
+    if let Some(prompt) = knowledge_prompt(e, quote_text, ctx).await {
+        vec.push(req::Message::new(req::Role::System, prompt));
+    }
+
+    // 网页与引用的消息来自他人，作为用户消息发送，不给予系统提示词的权限
+    if let Some(prompt) = fetch_prompt(e, quote_text, ctx).await {
+        vec.push(req::Message::new_with_user(prompt));
+    }
+
     if let Some(quote) = quote {
-        vec.push(req::Message::new_with_user(quote));
+        vec.push(req::Message::new_with_user(history::quote_prompt(quote)));
     }
 
     vec.push(req::Message::new_with_user(text.to_string()));
 
-    let res = chat_client.request_chat_completion(vec).await?;
+    let mut tools = tools::ToolRegistry::new();
+    if ctx.config.tools.calculator {
+        tools.register(calc::CalcTool);
+    }
+    if let Some(tool) = group_id(e)
+        .and_then(|id| history::HistoryTool::new(ctx.bot.clone(), id, &ctx.config.history))
+    {
+        tools.register(tool);
+    }
+    let citations = Arc::new(Mutex::new(Vec::new()));
+    if let Some(provider) = &ctx.search {
+        tools.register(search::SearchTool::new(
+            provider.clone(),
+            &ctx.config.search,
+            citations.clone(),
+        ));
+    }
 
-    res.content.ok_or("no content".into())
+    let mut res = ctx
+        .chat_client
+        .request_chat_completion(vec, &tools, params)
+        .await?;
+    let citations = citations.lock();
+    if !citations.is_empty() {
+        res.content = search::append_footnotes(&res.content, &citations);
+    }
+    Ok(res)
 }
 
-#[cfg(feature = "napcat-onebot")]
-async fn get_guote_text(
-    bot: &RuntimeBot,
-    _e: &MsgEvent,
-    quote: Vec<KoviSegment>,
-) -> Option<String> {
-    if quote.is_empty() {
+/// 读取问题与引用消息中的链接
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn fetch_prompt(e: &MsgEvent, quote: Option<&str>, ctx: &Context) -> Option<String> {
+    let fetcher = ctx.fetcher.as_ref()?;
+    let config = &ctx.config.fetch;
+    let urls = fetch::extract_urls(&query_text(e, ctx, quote));
+    if urls.is_empty() {
         return None;
     }
-    let quote = &quote[0];
-    let id = quote.data.get("id")?.as_str()?;
-    let mut quote_msg = bot.get_msg(id.parse().ok()?).await.ok()?;
-    let msg_json = quote_msg.data.get_mut("message")?.take();
-    let msg = kovi::Message::from_value(msg_json).ok()?;
 
-    let text = msg.to_human_string();
+    let mut pages = Vec::new();
+    let mut failed = Vec::new();
+    for url in urls.into_iter().take(config.max_urls) {
+        let page = if config.browser {
+            browser_page(fetcher, &url, ctx).await
+        } else {
+            fetcher.fetch(&url).await
+        };
+        match page {
+            Ok(page) if page.text.is_empty() => failed.push((url, "没有正文".to_string())),
+            Ok(page) => pages.push(page),
+            Err(err) => {
+                log::warn!("aiqa: Failed to fetch {}: {}", url, err);
+                failed.push((url, err));
+            }
+        }
+    }
+    if pages.is_empty() && failed.is_empty() {
+        return None;
+    }
+    Some(fetch::prompt(&pages, &failed, config.max_chars))
+}
 
-    Some(text)
+/// 用无头浏览器读取网页。浏览器的操作是阻塞的，放到单独的线程中执行。
+/// 只在打开标签页时锁住截图的浏览器，加载网页时其他人仍可渲染图片
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn browser_page(
+    fetcher: &fetch::Fetcher,
+    url: &str,
+    ctx: &Context,
+) -> Result<fetch::Page, String> {
+    let url = fetcher.check_browser(url).await?.to_string();
+    let filter = fetcher.browser_filter();
+    let screenshot = ctx.screenshot.clone();
+    let timeout = Duration::from_secs(ctx.config.fetch.timeout_secs);
+    kovi::tokio::task::spawn_blocking(move || {
+        let tab = screenshot.lock().new_tab().map_err(|err| err.to_string())?;
+        let (title, text) =
+            browser::page_text(&tab, &url, timeout, filter).map_err(|err| err.to_string())?;
+        Ok(fetch::Page {
+            url,
+            title: Some(title).filter(|v| !v.is_empty()),
+            text,
+        })
+    })
+    .await
+    .map_err(|err| err.to_string())?
 }
 
-#[cfg(feature = "milky")]
-async fn get_guote_text(bot: &RuntimeBot, e: &MsgEvent, quote: Vec<KoviSegment>) -> Option<String> {
-    use kovi_milky::MilkyMessageApi;
+/// 在知识库中检索与问题相关的片段
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+async fn knowledge_prompt(e: &MsgEvent, quote: Option<&str>, ctx: &Context) -> Option<String> {
+    let config = &ctx.config.knowledge;
+    if !config.enabled || ctx.knowledge.read().is_empty() {
+        return None;
+    }
+
+    let query = query_text(e, ctx, quote);
+
+    let embedding = match &config.embedding_model {
+        Some(model) if ctx.knowledge.read().has_embeddings() => ctx
+            .chat_client
+            .embed(model, vec![query.clone()])
+            .await
+            .ok()
+            .and_then(|mut v| v.pop()),
+        _ => None,
+    };
+
+    let base = ctx.knowledge.read();
+    let chunks = base.search(&query, embedding.as_deref(), group_id(e), config);
+    knowledge::prompt(&chunks, config.max_chars)
+}
+
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn group_id(e: &MsgEvent) -> Option<i64> {
+    e.get_group_id().and_then(|id| id.try_as_i64().copied())
+}
+
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn question_text<'a>(e: &'a MsgEvent, config: &Config) -> &'a str {
+    let text = e.borrow_text().unwrap_or_default();
+    match parse_prefix(text, config) {
+        Some((_, rest)) => flags::parse(rest).map_or(rest, |v| v.1).trim(),
+        None => text.trim(),
+    }
+}
 
-    if quote.is_empty() {
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn question_flags(e: &MsgEvent, config: &Config) -> Result<flags::Flags, error::FlagError> {
+    let text = e.borrow_text().unwrap_or_default();
+    match parse_prefix(text, config) {
+        Some((_, rest)) => flags::parse(rest).map(|v| v.0),
+        None => Ok(flags::Flags::default()),
+    }
+}
+
+/// 回答缓存的范围与键，未开启缓存、问题要求跳过缓存、指定了生成参数或需要读取链接时返回 None
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn cache_ref(
+    e: &MsgEvent,
+    ctx: &Context,
+    quote: Option<&str>,
+    flags: &flags::Flags,
+) -> Option<(String, String)> {
+    let config = &ctx.config.cache;
+    if !config.enabled || flags.no_cache || !flags.generation.is_empty() {
         return None;
     }
-    let quote = &quote[0];
-    let message_seq: i64 = quote.data.get("message_seq")?.as_i64()?;
+    // 网页内容会变化
+    if ctx.fetcher.is_some() && !fetch::extract_urls(&query_text(e, ctx, quote)).is_empty() {
+        return None;
+    }
+    // 回答可能用到群专属的知识库，有专属知识库的群即使共用缓存也只在本群复用
+    let own_knowledge = ctx.config.knowledge.enabled
+        && group_id(e).is_some_and(|id| ctx.knowledge.read().has_group(id));
+    let scope = match (config.per_group, group_id(e)) {
+        (false, Some(group_id)) if own_knowledge => format!("group:{}", group_id),
+        (false, _) => "global".to_string(),
+        (true, Some(group_id)) => format!("group:{}", group_id),
+        (true, None) => format!("user:{}", e.get_sender_id()),
+    };
+    Some((scope, cache::key(question_text(e, &ctx.config), quote)))
+}
+
+/// 问题连同引用的消息，用于检索与 embedding
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn query_text(e: &MsgEvent, ctx: &Context, quote: Option<&str>) -> String {
+    match quote {
+        Some(quote) => format!("{}\n{}", quote, question_text(e, &ctx.config)),
+        None => question_text(e, &ctx.config).to_string(),
+    }
+}
 
-    let group_id = e.data.group.as_ref()?.group_id;
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+fn page_meta(
+    e: &MsgEvent,
+    config: &Config,
+    quote: Option<String>,
+    res: &req::Completion,
+) -> html::PageMeta {
+    let render = &config.render;
+    let mut meta = html::PageMeta::default();
+
+    if render.show_question {
+        meta.question = Some(truncate_chars(
+            question_text(e, config),
+            render.question_max_chars,
+        ));
+    }
+    let avatar = e
+        .get_sender_id()
+        .try_as_i64()
+        .map(|id| format!("https://q1.qlogo.cn/g?b=qq&nk={}&s=100", id));
+    if render.show_sender {
+        meta.nickname = e.get_sender_name().map(|v| v.to_string());
+        meta.avatar = avatar.clone();
+    }
+    if render.show_chat_card {
+        meta.chat = Some(html::ChatCard {
+            nickname: e.get_sender_name().unwrap_or_default().to_string(),
+            avatar,
+            question: question_text(e, config).to_string(),
+            quote: quote.map(|v| truncate_chars(&v, render.quote_max_chars)),
+        });
+    }
+    if let Some(reasoning) = &res.reasoning {
+        let max_chars = config.reasoning.max_chars;
+        meta.reasoning = match config.reasoning.display {
+            ReasoningDisplay::Collapsed => Some(html::ReasoningCard {
+                text: reasoning.clone(),
+                chars: reasoning.chars().count(),
+                open: false,
+            }),
+            ReasoningDisplay::Grey => Some(html::ReasoningCard {
+                text: truncate_chars(reasoning, max_chars),
+                chars: reasoning.chars().count(),
+                open: true,
+            }),
+            ReasoningDisplay::Hide | ReasoningDisplay::Forward => None,
+        };
+    }
+    if render.show_model {
+        meta.model = Some(res.model.clone());
+    }
+    if render.show_usage {
+        meta.usage = res.usage.as_ref().map(|usage| {
+            format!(
+                "{} tokens ({} + {})",
+                usage.total_tokens, usage.prompt_tokens, usage.completion_tokens
+            )
+        });
+    }
+    if render.show_latency {
+        meta.latency = Some(format!("{:.1}s", res.latency.as_secs_f64()));
+    }
+    if render.show_time {
+        meta.time = Some(chrono::Local::now().format("%Y-%m-%d %H:%M").to_string());
+    }
 
-    let mut quote_msg = bot.get_message("group", group_id, message_seq).await.ok()?;
-    let msg_json = quote_msg.data.get_mut("message")?.take();
-    let msg = kovi::Message::from_value(msg_json).ok()?;
+    meta
+}
 
-    let text = msg.to_human_string();
+/// 用标签包裹网页、引用消息等外部内容，与提问本身区分开。
+/// 去掉内容中的同名标签，避免内容提前结束包裹
+fn enclose(tag: &str, text: &str) -> String {
+    let text = text
+        .replace(&format!("<{}>", tag), "")
+        .replace(&format!("</{}>", tag), "");
+    format!("<{tag}>\n{text}\n</{tag}>")
+}
 
-    Some(text)
+fn truncate_chars(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    let mut out: String = s.chars().take(max).collect();
+    out.push('…');
+    out
+}
+
+/// 获取被引用的消息
+#[cfg(feature = "napcat-onebot")]
+async fn get_quote(
+    bot: &RuntimeBot,
+    config: &HistoryConfig,
+    _e: &MsgEvent,
+    quote: Vec<KoviSegment>,
+) -> Option<history::HistoryMessage> {
+    let quote = quote.first()?;
+    let id: i64 = quote.data.get("id")?.as_str()?.parse().ok()?;
+    history::fetch_quote(bot, config, id)
+        .await
+        .inspect_err(|err| log::warn!("aiqa: Failed to get quoted message: {}", err))
+        .ok()
+        .flatten()
+}
+
+/// 获取被引用的消息
+#[cfg(feature = "milky")]
+async fn get_quote(
+    bot: &RuntimeBot,
+    config: &HistoryConfig,
+    e: &MsgEvent,
+    quote: Vec<KoviSegment>,
+) -> Option<history::HistoryMessage> {
+    let quote = quote.first()?;
+    let message_seq: i64 = quote.data.get("message_seq")?.as_i64()?;
+
+    let (scene, peer_id) = message_scene(e)?;
+
+    history::fetch_quote(bot, config, scene, peer_id, message_seq)
+        .await
+        .inspect_err(|err| log::warn!("aiqa: Failed to get quoted message: {}", err))
+        .ok()
+        .flatten()
 }
 
 fn image_to_base64(img: Vec<u8>) -> String {
     STANDARD.encode(&img)
 }
 
-fn md_to_html(md: &str) -> String {
-    let mut options = pulldown_cmark::Options::empty();
-    options.insert(Options::ENABLE_STRIKETHROUGH);
-    options.insert(Options::ENABLE_TABLES);
-    options.insert(Options::ENABLE_FOOTNOTES);
-    options.insert(Options::ENABLE_MATH);
-    options.insert(Options::ENABLE_GFM);
-    let parser = pulldown_cmark::Parser::new_ext(md, options);
+fn md_to_html(md: &str, meta: &html::PageMeta, template: &html::Template) -> String {
+    let parser = pulldown_cmark::Parser::new_ext(md, markdown::options());
 
-    let mut html_output = String::new();
-    html_output.push_str(html::HTML_START_NEXT_IS_MD_CSS);
-    if *LIGHT.read() {
-        html_output.push_str(html::GITHUB_MARKDOWN_LIGHT_NEXT_IS_HTML2);
-    } else {
-        html_output.push_str(html::GITHUB_MARKDOWN_DARK_NEXT_IS_HTML2);
-    }
-    html_output.push_str(html::HTML_2_NEXT_IS_HIGHLIGHT_CSS);
-    if *LIGHT.read() {
-        html_output.push_str(html::HIGH_LIGHT_LIGHT_CSS_NEXT_IS_HTML3);
-    } else {
-        html_output.push_str(html::HIGH_LIGHT_DARK_CSS_NEXT_IS_HTML3);
-    }
-    html_output.push_str(html::HTML_3_NEXT_IS_MD_BODY_AND_THEN_IS_HTML4);
-    pulldown_cmark::html::push_html(&mut html_output, parser);
-    html_output.push_str(html::HTML_4_NEXT_IS_HIGH_LIGHT_JS);
-    html_output.push_str(html::HIGH_LIGHT_JS_NEXT_IS_HTML_END);
-    html_output.push_str(html::HTML_END);
+    let mut body = String::new();
+    pulldown_cmark::html::push_html(&mut body, parser);
 
-    html_output
+    let (theme, markdown_css, highlight_css) = if *LIGHT.read() {
+        (
+            "light",
+            html::GITHUB_MARKDOWN_LIGHT_CSS,
+            html::HIGH_LIGHT_LIGHT_CSS,
+        )
+    } else {
+        (
+            "dark",
+            html::GITHUB_MARKDOWN_DARK_CSS,
+            html::HIGH_LIGHT_DARK_CSS,
+        )
+    };
+    let css = format!("{}\n{}\n{}", markdown_css, html::BASE_CSS, highlight_css);
+
+    let escaped = |v: &Option<String>| v.as_deref().map(html::escape).unwrap_or_default();
+    let question = escaped(&meta.question);
+    let nickname = escaped(&meta.nickname);
+    let avatar = escaped(&meta.avatar);
+    let model = escaped(&meta.model);
+    let usage = escaped(&meta.usage);
+    let latency = escaped(&meta.latency);
+    let time = escaped(&meta.time);
+
+    template.render(&[
+        ("css", &css),
+        ("markdown_css", markdown_css),
+        ("highlight_css", highlight_css),
+        ("base_css", html::BASE_CSS),
+        ("theme", theme),
+        ("body", &body),
+        ("header", &meta.header_html()),
+        ("chat", &meta.chat_html()),
+        ("reasoning", &meta.reasoning_html()),
+        ("footer", &meta.footer_html()),
+        ("question", &question),
+        ("nickname", &nickname),
+        ("avatar", &avatar),
+        ("model", &model),
+        ("usage", &usage),
+        ("latency", &latency),
+        ("time", &time),
+        ("scripts", &html::SCRIPTS),
+        ("highlight_js", html::HIGH_LIGHT_JS),
+    ])
 }
 
 #[test]
@@ -397,7 +1343,100 @@ But let's throw in a <b>tag</b>.
 已知过点$A(-1, 0)$ 、 $B(1, 0)$两点的动抛物线的准线始终与圆$x^2 + y^2 = 9$相切，该抛物线焦点$P$的轨迹是某圆锥曲线$E$的一部分。<br>(1)求曲线$E$的标准方程；<br>(2)已知点$C(-3, 0)$ ， $D(2, 0)$ ，过点$D$的动直线与曲线$E$相交于$M$ 、 $N$ ，设$\triangle CMN$的外心为$Q$ ， $O$为坐标原点，问：直线$OQ$与直线$MN$的斜率之积是否为定值，如果为定值，求出该定值；如果不是定值，则说明理由。
 "#;
 
-    let res = md_to_html(md);
+    let res = md_to_html(md, &html::PageMeta::default(), &html::Template::bundled());
+    assert!(res.contains("<h1>你好呀!</h1>"));
+    assert!(res.contains("&lt;b&gt;tag&lt;/b&gt;"));
+    assert!(!res.contains(r#"<header class="aiqa-header">"#));
+    assert!(!res.contains(r#"<footer class="aiqa-footer">"#));
+
+    let meta = html::PageMeta {
+        question: Some("<script>提问</script>".to_string()),
+        nickname: Some("小明".to_string()),
+        model: Some("gpt".to_string()),
+        time: Some("1.2s".to_string()),
+        ..html::PageMeta::default()
+    };
+    let res = md_to_html(md, &meta, &html::Template::bundled());
+    assert!(res.contains(r#"<div class="aiqa-nickname">小明</div>"#));
+    assert!(res.contains(r#"<div class="aiqa-question">&lt;script&gt;提问&lt;/script&gt;</div>"#));
+    assert!(res.contains(r#"<footer class="aiqa-footer">gpt · 1.2s</footer>"#));
+    // 页眉在正文之前，页脚在正文之后
+    let header = res.find("aiqa-header\"").unwrap();
+    let body = res.find("<h1>").unwrap();
+    let footer = res.find("aiqa-footer\"").unwrap();
+    assert!(header < body && body < footer);
+}
+
+#[test]
+fn test_template() {
+    let template = html::Template::parse(
+        "<a>{{ body }}</a>{{#if model}}<i>{{model}}</i>{{/if}}{{#if time}}{{time}}{{/if}}",
+    )
+    .unwrap();
+
+    let res = template.render(&[("body", "{{body}} & {{time}}"), ("model", "gpt")]);
+    assert_eq!(res, "<a>{{body}} & {{time}}</a><i>gpt</i>");
+
+    assert!(html::Template::bundled().validate().is_ok());
+    assert!(html::Template::parse("{{#if body}}").is_err());
+    assert!(html::Template::parse("{{unknown}}").is_err());
+
+    let no_scripts =
+        html::Template::parse(r#"<article class="markdown-body">{{body}}</article>"#).unwrap();
+    assert!(no_scripts.validate().is_err());
 
-    std::fs::write("output.html", &res).unwrap();
+    let outside =
+        html::Template::parse(r#"<article class="markdown-body"></article>{{body}}{{scripts}}"#)
+            .unwrap();
+    assert!(outside.validate().is_err());
+
+    let conditional = html::Template::parse(
+        r#"{{#if model}}<article class="markdown-body">{{body}}</article>{{scripts}}{{/if}}"#,
+    )
+    .unwrap();
+    assert!(conditional.validate().is_err());
+    let body_if = html::Template::parse(
+        r#"<article class="markdown-body">{{#if body}}{{body}}{{/if}}</article>{{scripts}}"#,
+    )
+    .unwrap();
+    assert!(body_if.validate().is_ok());
+
+    // 全部有值与全部为空时都成立，只有 `time` 为空时 `{{body}}` 不在 article 内
+    let mixed = html::Template::parse(
+        r#"<article class="markdown-body">{{#if model}}</article>{{/if}}{{#if time}}<article class="markdown-body">{{/if}}{{body}}</article>{{scripts}}"#,
+    )
+    .unwrap();
+    assert!(mixed.validate().is_err());
+}
+
+#[cfg(any(feature = "napcat-onebot", feature = "milky"))]
+#[test]
+fn test_parse_prefix() {
+    let mut config = Config::default();
+    assert_eq!(
+        parse_prefix("%你好", &config),
+        Some((OutputMode::Image, "你好"))
+    );
+    assert_eq!(
+        parse_prefix("%%你好", &config),
+        Some((OutputMode::Text, "你好"))
+    );
+    assert_eq!(parse_prefix("?你好", &config), None);
+    assert_eq!(parse_prefix("你好", &config), None);
+
+    // default_mode 不影响 `%` 与 `%%`
+    config.default_mode = OutputMode::Auto;
+    config.mode_cmd = Some('?');
+    assert_eq!(
+        parse_prefix("%你好", &config),
+        Some((OutputMode::Image, "你好"))
+    );
+    assert_eq!(
+        parse_prefix("%%你好", &config),
+        Some((OutputMode::Text, "你好"))
+    );
+    assert_eq!(
+        parse_prefix("?你好", &config),
+        Some((OutputMode::Auto, "你好"))
+    );
 }
@@ -0,0 +1,647 @@
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+/// 所有 markdown 解析共用的选项，与图片渲染保持一致
+pub fn options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_MATH);
+    options.insert(Options::ENABLE_GFM);
+    options
+}
+
+/// 回答中影响展示方式的特征
+#[derive(Debug, Default)]
+pub struct Features {
+    pub code_block: bool,
+    pub table: bool,
+    pub math: bool,
+    pub chars: usize,
+    pub lines: usize,
+}
+
+pub fn analyze(md: &str) -> Features {
+    let mut features = Features {
+        chars: md.trim().chars().count(),
+        lines: md.lines().filter(|line| !line.trim().is_empty()).count(),
+        ..Default::default()
+    };
+
+    for event in Parser::new_ext(md, options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => features.code_block = true,
+            Event::Start(Tag::Table(_)) => features.table = true,
+            Event::InlineMath(_) | Event::DisplayMath(_) => features.math = true,
+            _ => {}
+        }
+    }
+
+    features
+}
+
+/// 将 markdown 按顶层块切分为不超过 `max_chars` 个字符的若干段。
+///
+/// 只在块与块之间切分，含有代码块的块即使超长也保持完整；超长的其它块按行切分。
+/// `max_chars` 为 0 时按 1 处理。
+pub fn split(md: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    // 顶层块的起始位置，以及块内（包括列表、引用中）是否有代码块
+    let mut cuts = Vec::new();
+    let mut has_code = Vec::new();
+    let mut depth = 0usize;
+    for (event, range) in Parser::new_ext(md, options()).into_offset_iter() {
+        match event {
+            Event::Start(tag) => {
+                if depth == 0 {
+                    cuts.push(range.start);
+                    has_code.push(false);
+                }
+                if matches!(tag, Tag::CodeBlock(_)) {
+                    *has_code.last_mut().unwrap() = true;
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth -= 1,
+            _ if depth == 0 => {
+                cuts.push(range.start);
+                has_code.push(false);
+            }
+            _ => {}
+        }
+    }
+    // 第一个块之前可能有不产生事件的内容（如链接引用定义），并入第一个块
+    match cuts.first_mut() {
+        Some(first) => *first = 0,
+        None => {
+            cuts.push(0);
+            has_code.push(false);
+        }
+    }
+    cuts.push(md.len());
+
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for (window, &is_code) in cuts.windows(2).zip(has_code.iter()) {
+        let block = md[window[0]..window[1]].trim_end();
+        if block.trim().is_empty() {
+            continue;
+        }
+
+        let pieces = if is_code || block.chars().count() <= max_chars {
+            vec![block.to_string()]
+        } else {
+            split_lines(block, max_chars)
+        };
+
+        for piece in pieces {
+            if !current.is_empty()
+                && current.chars().count() + piece.chars().count() + 2 > max_chars
+            {
+                chunks.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(&piece);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+fn split_lines(block: &str, max_chars: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for line in block.lines() {
+        let mut line = line;
+        // 单行超长时只能按字符切
+        while line.chars().count() > max_chars {
+            let at = line
+                .char_indices()
+                .nth(max_chars)
+                .map(|(i, _)| i)
+                .unwrap_or(line.len());
+            if !current.is_empty() {
+                pieces.push(std::mem::take(&mut current));
+            }
+            pieces.push(line[..at].to_string());
+            line = &line[at..];
+        }
+        if !current.is_empty() && current.chars().count() + line.chars().count() + 1 > max_chars {
+            pieces.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+pub struct CodeBlock {
+    pub lang: String,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// 根据代码块标注的语言推断文件扩展名
+    pub fn extension(&self) -> &'static str {
+        match self.lang.to_ascii_lowercase().as_str() {
+            "rust" | "rs" => "rs",
+            "python" | "py" => "py",
+            "javascript" | "js" => "js",
+            "typescript" | "ts" => "ts",
+            "jsx" => "jsx",
+            "tsx" => "tsx",
+            "c" => "c",
+            "cpp" | "c++" | "cxx" => "cpp",
+            "csharp" | "c#" | "cs" => "cs",
+            "java" => "java",
+            "kotlin" | "kt" => "kt",
+            "go" | "golang" => "go",
+            "swift" => "swift",
+            "ruby" | "rb" => "rb",
+            "php" => "php",
+            "lua" => "lua",
+            "bash" | "sh" | "shell" | "zsh" => "sh",
+            "powershell" | "ps1" => "ps1",
+            "bat" | "cmd" => "bat",
+            "sql" => "sql",
+            "html" => "html",
+            "css" => "css",
+            "json" => "json",
+            "yaml" | "yml" => "yml",
+            "toml" => "toml",
+            "xml" => "xml",
+            "markdown" | "md" => "md",
+            _ => "txt",
+        }
+    }
+}
+
+/// 提取回答中所有围栏代码块
+pub fn code_blocks(md: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+
+    for event in Parser::new_ext(md, options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) => {
+                current = Some(CodeBlock {
+                    // 围栏后可能还有其它信息，如 ```rust,ignore
+                    lang: lang
+                        .split([',', ' '])
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    code: String::new(),
+                });
+            }
+            Event::Text(text) => {
+                if let Some(block) = &mut current {
+                    block.code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some(block) = current.take()
+                    && !block.code.trim().is_empty()
+                {
+                    blocks.push(block);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+/// 将 markdown 转换为适合直接发送的纯文本
+///
+/// 去掉强调等标记，列表使用圆点，代码块缩进，简单表格按列对齐，公式保留 TeX，链接写作 `文字 (url)`。
+pub fn to_plain_text(md: &str) -> String {
+    let mut writer = PlainWriter::default();
+    for event in Parser::new_ext(md, options()) {
+        writer.event(event);
+    }
+
+    writer
+        .out
+        .lines()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// 回答第一段的纯文本，用作摘要
+pub fn first_paragraph(md: &str) -> String {
+    to_plain_text(md)
+        .split("\n\n")
+        .map(str::trim)
+        .find(|paragraph| !paragraph.is_empty())
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[derive(Default)]
+struct PlainTable {
+    aligns: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+    cell: String,
+}
+
+#[derive(Default)]
+struct PlainWriter {
+    out: String,
+    /// 每层列表下一个序号，无序列表为 None
+    lists: Vec<Option<u64>>,
+    quote_depth: usize,
+    /// 刚写完列表符号，接下来的块不需要换行
+    item_start: bool,
+    /// 链接地址与链接文字在输出中的起始位置
+    links: Vec<(String, usize)>,
+    code: Option<String>,
+    table: Option<PlainTable>,
+}
+
+impl PlainWriter {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => {
+                if let Some(code) = &mut self.code {
+                    code.push_str(&text);
+                } else {
+                    self.text(&text);
+                }
+            }
+            Event::Code(text) => self.text(&text),
+            Event::InlineMath(text) => self.text(&format!("${}$", text)),
+            Event::DisplayMath(text) => self.text(&format!("$${}$$", text)),
+            Event::Html(html) | Event::InlineHtml(html) => {
+                let tag = html.trim().to_ascii_lowercase();
+                if tag.starts_with("<br") {
+                    self.line_break();
+                }
+            }
+            Event::FootnoteReference(label) => self.text(&format!("[{}]", label)),
+            Event::SoftBreak | Event::HardBreak => {
+                if let Some(table) = &mut self.table {
+                    table.cell.push(' ');
+                } else {
+                    self.line_break();
+                }
+            }
+            Event::Rule => {
+                self.block_gap();
+                self.text("──────────");
+            }
+            Event::TaskListMarker(checked) => self.text(if checked { "☑ " } else { "☐ " }),
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::Heading { .. } | Tag::HtmlBlock => self.block_gap(),
+            Tag::BlockQuote(_) => {
+                self.block_gap();
+                self.quote_depth += 1;
+                self.out.push_str("│ ");
+            }
+            Tag::CodeBlock(kind) => {
+                self.block_gap();
+                if let CodeBlockKind::Fenced(lang) = kind
+                    && !lang.is_empty()
+                {
+                    self.text(&lang);
+                    self.line_break();
+                }
+                self.code = Some(String::new());
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.block_gap();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                // 当前行只有前缀时直接在这一行写列表符号
+                let line_start = self.out.rfind('\n').map(|i| i + 1).unwrap_or(0);
+                if self.out[line_start..].trim_matches(['│', ' ']).is_empty() {
+                    self.out.truncate(line_start);
+                } else {
+                    self.out.push('\n');
+                }
+                self.out.push_str(&"│ ".repeat(self.quote_depth));
+                self.out
+                    .push_str(&"  ".repeat(self.lists.len().saturating_sub(1)));
+                let depth = self.lists.len();
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ if depth > 1 => "◦ ".to_string(),
+                    _ => "• ".to_string(),
+                };
+                self.out.push_str(&bullet);
+                self.item_start = true;
+            }
+            Tag::FootnoteDefinition(label) => {
+                self.block_gap();
+                self.text(&format!("[{}] ", label));
+                self.item_start = true;
+            }
+            Tag::Table(aligns) => {
+                self.block_gap();
+                self.table = Some(PlainTable {
+                    aligns,
+                    ..Default::default()
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                let start = match &self.table {
+                    Some(table) => table.cell.len(),
+                    None => self.out.len(),
+                };
+                self.links.push((dest_url.to_string(), start));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::BlockQuote(_) => self.quote_depth -= 1,
+            TagEnd::CodeBlock => {
+                let code = self.code.take().unwrap_or_default();
+                let mut lines = code.trim_end_matches('\n').lines().peekable();
+                while let Some(line) = lines.next() {
+                    self.out.push_str("    ");
+                    self.out.push_str(line);
+                    if lines.peek().is_some() {
+                        self.line_break();
+                    }
+                }
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+            }
+            TagEnd::TableCell => {
+                if let Some(table) = &mut self.table {
+                    let cell = std::mem::take(&mut table.cell);
+                    if let Some(row) = table.rows.last_mut() {
+                        row.push(cell.trim().to_string());
+                    }
+                }
+            }
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.write_table(table);
+                }
+            }
+            TagEnd::Link | TagEnd::Image => {
+                if let Some((url, start)) = self.links.pop() {
+                    let target = match &mut self.table {
+                        Some(table) => &mut table.cell,
+                        None => &mut self.out,
+                    };
+                    if target[start..].trim() != url {
+                        target.push_str(&format!(" ({})", url));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        self.item_start = false;
+        if let Some(table) = &mut self.table {
+            table.cell.push_str(text);
+            return;
+        }
+        let mut lines = text.split('\n');
+        if let Some(first) = lines.next() {
+            self.out.push_str(first);
+        }
+        for line in lines {
+            self.line_break();
+            self.out.push_str(line);
+        }
+    }
+
+    fn prefix(&self) -> String {
+        format!(
+            "{}{}",
+            "│ ".repeat(self.quote_depth),
+            "  ".repeat(self.lists.len())
+        )
+    }
+
+    fn line_break(&mut self) {
+        self.out.push('\n');
+        self.out.push_str(&self.prefix());
+    }
+
+    /// 在块之间插入空行，列表内只换行
+    fn block_gap(&mut self) {
+        if self.out.is_empty() || self.item_start {
+            return;
+        }
+        if self.out.ends_with("│ ") && self.out.trim_end().ends_with('│') {
+            // 引用块刚开始
+            return;
+        }
+        if self.lists.is_empty() {
+            self.line_break();
+        }
+        self.line_break();
+    }
+
+    fn write_table(&mut self, table: PlainTable) {
+        let columns = table.rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in &table.rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(display_width(cell));
+            }
+        }
+
+        for (i, row) in table.rows.iter().enumerate() {
+            if i > 0 {
+                self.line_break();
+            }
+            let cells: Vec<String> = (0..columns)
+                .map(|col| {
+                    let cell = row.get(col).map(String::as_str).unwrap_or_default();
+                    let pad = widths[col] - display_width(cell);
+                    match table.aligns.get(col) {
+                        Some(Alignment::Right) => format!("{}{}", " ".repeat(pad), cell),
+                        Some(Alignment::Center) => format!(
+                            "{}{}{}",
+                            " ".repeat(pad / 2),
+                            cell,
+                            " ".repeat(pad - pad / 2)
+                        ),
+                        _ => format!("{}{}", cell, " ".repeat(pad)),
+                    }
+                })
+                .collect();
+            self.out.push_str(&cells.join("  "));
+
+            if i == 0 {
+                self.line_break();
+                let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+                self.out.push_str(&rule.join("  "));
+            }
+        }
+        self.item_start = false;
+    }
+}
+
+/// 粗略的显示宽度，中日韩文字与全角符号按 2 计算
+fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c as u32 {
+            0x1100..=0x115F
+            | 0x2E80..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+#[test]
+fn test_analyze() {
+    let plain = analyze("1+1 等于 **2**。");
+    assert!(!plain.code_block && !plain.table && !plain.math);
+    assert_eq!(plain.lines, 1);
+
+    let rich = analyze("| a | b |\n|---|---|\n| 1 | 2 |\n\n$x^2$\n\n```rust\nfn main() {}\n```");
+    assert!(rich.code_block && rich.table && rich.math);
+
+    // 行内代码不算代码块
+    assert!(!analyze("使用 `cargo build` 编译").code_block);
+}
+
+#[test]
+fn test_split() {
+    let code = "```python\nfor i in range(10):\n\n    print(i)\n```";
+    let md = format!(
+        "# 标题\n\n第一段\n\n{}\n\n- 列表一\n- 列表二\n\n最后一段",
+        code
+    );
+
+    let chunks = split(&md, 20);
+    assert!(
+        chunks
+            .iter()
+            .all(|chunk| chunk.chars().count() <= 20 || chunk == code)
+    );
+    assert!(chunks.contains(&code.to_string()));
+    assert_eq!(chunks.join("\n\n"), md);
+
+    assert_eq!(split(&md, 1000), vec![md.clone()]);
+    assert!(
+        split(&"很长".repeat(50), 30)
+            .iter()
+            .all(|c| c.chars().count() <= 30)
+    );
+    assert_eq!(split("ab", 0), vec!["a", "b"]);
+
+    // 列表与引用中的代码块也不会被切开
+    let nested = "- 示例：\n\n  ```python\n  line1\n  line2\n  ```\n\n> ```\n> quoted code\n> ```";
+    let chunks = split(nested, 10);
+    assert_eq!(
+        chunks,
+        vec![
+            "- 示例：\n\n  ```python\n  line1\n  line2\n  ```",
+            "> ```\n> quoted code\n> ```"
+        ]
+    );
+
+    // 开头的链接引用定义不会丢失
+    let refs = "[doc]: https://example.com\n\n见 [文档][doc]。";
+    assert_eq!(split(refs, 1000), vec![refs]);
+    assert_eq!(
+        split("[doc]: https://example.com", 1000),
+        vec!["[doc]: https://example.com"]
+    );
+}
+
+#[test]
+fn test_to_plain_text() {
+    let md = r#"### 标题
+
+这是 **加粗** 和 *斜体*，见 [文档](https://example.com)。
+
+- 第一项
+- 第二项
+  1. 子项
+
+```rust
+fn main() {}
+```
+
+| 名称 | 数量 |
+|:---|---:|
+| 苹果 | 3 |
+| pear | 12 |
+
+公式 $x^2$"#;
+
+    let expected = r#"标题
+
+这是 加粗 和 斜体，见 文档 (https://example.com)。
+
+• 第一项
+• 第二项
+  1. 子项
+
+rust
+    fn main() {}
+
+名称  数量
+----  ----
+苹果     3
+pear    12
+
+公式 $x^2$"#;
+
+    assert_eq!(to_plain_text(md), expected);
+}
+
+#[test]
+fn test_code_blocks() {
+    let md = "看代码：\n\n```python\nprint(1)\n```\n\n```\nplain\n```\n\n```rust,ignore\nfn main() {}\n```\n\n    indented";
+    let blocks = code_blocks(md);
+
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(blocks[0].code, "print(1)\n");
+    assert_eq!(blocks[0].extension(), "py");
+    assert_eq!(blocks[1].extension(), "txt");
+    assert_eq!(blocks[2].extension(), "rs");
+}
@@ -0,0 +1,249 @@
+//! 测试用的模型接口，按顺序返回预设的响应并记录收到的请求
+
+use crate::Config;
+use crate::req::{ChatClient, Completion};
+use kovi::serde_json::{self, Value};
+use kovi::tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use kovi::tokio::net::TcpListener;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 请求 `base_url` 的测试配置
+pub fn config(base_url: &str) -> Config {
+    Config {
+        apikey: Some("sk-test".to_string()),
+        base_url: Some(base_url.to_string()),
+        model_name: Some("mock-model".to_string()),
+        ..Config::default()
+    }
+}
+
+/// 请求 `base_url` 的客户端
+pub fn client(base_url: &str) -> ChatClient {
+    ChatClient::new(&config(base_url))
+}
+
+/// 一个普通回答，没有用量信息
+pub fn completion(content: &str) -> Completion {
+    Completion {
+        content: content.to_string(),
+        model: "mock-model".to_string(),
+        usage: None,
+        latency: Duration::ZERO,
+        used_tools: false,
+        provider: String::new(),
+        reasoning: None,
+    }
+}
+
+pub struct Response {
+    status: u16,
+    body: Value,
+    headers: Vec<(String, String)>,
+    /// 返回响应前等待的时间，用于测试超时
+    delay: Duration,
+}
+
+impl Response {
+    /// 非 JSON 的响应，如网页
+    pub fn text(status: u16, content_type: &str, body: &str) -> Response {
+        Response::from((status, Value::String(body.to_string())))
+            .header("content-type", content_type)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Response {
+        self.delay = delay;
+        self
+    }
+}
+
+impl From<(u16, Value)> for Response {
+    fn from((status, body): (u16, Value)) -> Response {
+        Response {
+            status,
+            body,
+            headers: Vec::new(),
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+pub struct MockServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<Value>>>,
+    /// 每个请求的请求行与请求头
+    heads: Arc<Mutex<Vec<Head>>>,
+}
+
+#[derive(Clone, Default)]
+pub struct Head {
+    /// 如 `POST /v1/chat/completions`
+    pub line: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl MockServer {
+    /// 启动服务，每个请求依次返回 `responses` 中的一个，如（状态码，响应体）
+    pub async fn start<R: Into<Response>>(responses: Vec<R>) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let heads = Arc::new(Mutex::new(Vec::new()));
+        let responses: VecDeque<Response> = responses.into_iter().map(Into::into).collect();
+        let responses = Arc::new(Mutex::new(responses));
+
+        let requests_ = requests.clone();
+        let heads_ = heads.clone();
+        kovi::tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let requests = requests_.clone();
+                let heads = heads_.clone();
+                let responses = responses.clone();
+                kovi::tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    loop {
+                        let mut length = 0;
+                        let mut head = Head::default();
+                        let mut line = String::new();
+                        // 请求行与请求头
+                        loop {
+                            line.clear();
+                            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                            if head.line.is_empty() {
+                                head.line = line.rsplit_once(' ').map_or("", |v| v.0).to_string();
+                            } else if let Some((k, v)) = line.split_once(':') {
+                                head.headers.push((k.to_string(), v.trim().to_string()));
+                            }
+                            let lower = line.to_ascii_lowercase();
+                            if let Some(v) = lower.strip_prefix("content-length:") {
+                                length = v.trim().parse().unwrap_or(0);
+                            }
+                        }
+
+                        let mut body = vec![0; length];
+                        if stream.read_exact(&mut body).await.is_err() {
+                            return;
+                        }
+                        heads.lock().unwrap().push(head);
+                        requests
+                            .lock()
+                            .unwrap()
+                            .push(serde_json::from_slice(&body).unwrap_or(Value::Null));
+
+                        let response = responses
+                            .lock()
+                            .unwrap()
+                            .pop_front()
+                            .unwrap_or_else(|| (500, Value::Null).into());
+                        kovi::tokio::time::sleep(response.delay).await;
+                        let custom_type = response
+                            .headers
+                            .iter()
+                            .any(|(k, _)| k.eq_ignore_ascii_case("content-type"));
+                        let body = match (&response.body, custom_type) {
+                            (Value::String(text), true) => text.clone(),
+                            (body, _) => body.to_string(),
+                        };
+                        let mut head = format!(
+                            "HTTP/1.1 {} MOCK\r\ncontent-length: {}\r\n",
+                            response.status,
+                            body.len()
+                        );
+                        if !custom_type {
+                            head.push_str("content-type: application/json\r\n");
+                        }
+                        for (name, value) in response.headers.iter() {
+                            head.push_str(&format!("{}: {}\r\n", name, value));
+                        }
+                        head.push_str("\r\n");
+                        let stream = stream.get_mut();
+                        if stream.write_all(head.as_bytes()).await.is_err()
+                            || stream.write_all(body.as_bytes()).await.is_err()
+                        {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        MockServer {
+            base_url,
+            requests,
+            heads,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn heads(&self) -> Vec<Head> {
+        self.heads.lock().unwrap().clone()
+    }
+}
+
+/// 一个普通回答的响应体
+pub fn answer(content: &str) -> Value {
+    serde_json::json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "mock-model",
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "finish_reason": "stop"
+        }],
+        "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 }
+    })
+}
+
+/// 调用工具的响应体，`calls` 为（调用 id，工具名，参数）
+pub fn tool_calls(calls: &[(&str, &str, Value)]) -> Value {
+    let calls: Vec<Value> = calls
+        .iter()
+        .map(|(id, name, args)| {
+            serde_json::json!({
+                "id": id,
+                "type": "function",
+                "function": { "name": name, "arguments": args.to_string() }
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "mock-model",
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": null, "tool_calls": calls },
+            "finish_reason": "tool_calls"
+        }],
+        "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 }
+    })
+}
@@ -0,0 +1,336 @@
+//! Ollama 原生的 `/api/chat` 接口
+
+use crate::config::{GenerationConfig, OllamaConfig};
+use crate::error::RequestError;
+use crate::provider::{self, FinishReason, ModelInfo, Provider, Reasoning, Reply};
+use crate::req::{Message, Role, ToolCall};
+use crate::tools::ToolRegistry;
+use kovi::serde_json::{self, Value, json};
+
+pub struct Ollama {
+    keep_alive: Option<String>,
+    options: serde_json::Map<String, Value>,
+}
+
+impl Ollama {
+    pub fn new(config: &OllamaConfig) -> Ollama {
+        let mut options = config.options.clone();
+        if let Some(num_ctx) = config.num_ctx {
+            options.insert("num_ctx".to_string(), num_ctx.into());
+        }
+        Ollama {
+            keep_alive: config.keep_alive.clone(),
+            options,
+        }
+    }
+}
+
+impl Provider for Ollama {
+    fn chat_path(&self) -> &'static str {
+        "/api/chat"
+    }
+
+    fn chat_request(
+        &self,
+        model: &str,
+        system: &str,
+        msgs: &[Message],
+        tools: &ToolRegistry,
+        use_tools: bool,
+        params: &GenerationConfig,
+    ) -> Value {
+        let mut messages = vec![json!({ "role": "system", "content": system })];
+
+        for msg in msgs.iter() {
+            messages.push(match msg.role {
+                Role::System => json!({ "role": "system", "content": msg.content }),
+                Role::User => json!({ "role": "user", "content": msg.content }),
+                Role::Assistant => {
+                    let mut message = json!({ "role": "assistant", "content": msg.content });
+                    if !msg.tool_calls.is_empty() {
+                        // 参数是对象而不是 JSON 字符串
+                        message["tool_calls"] = msg
+                            .tool_calls
+                            .iter()
+                            .map(|call| {
+                                json!({
+                                    "function": {
+                                        "name": call.name,
+                                        "arguments": serde_json::from_str::<Value>(&call.arguments)
+                                            .unwrap_or(json!({})),
+                                    }
+                                })
+                            })
+                            .collect();
+                    }
+                    message
+                }
+                // Ollama 的工具调用没有 id，用工具名对应调用
+                Role::Tool => json!({
+                    "role": "tool",
+                    "content": msg.content,
+                    "tool_name": tool_name(msgs, msg.tool_call_id.as_deref()),
+                }),
+            });
+        }
+
+        let mut request = json!({
+            "model": model,
+            "messages": messages,
+            "stream": false,
+        });
+        // Ollama 只能开关思考，不区分程度
+        if params.reasoning_effort.is_some() {
+            request["think"] = true.into();
+        }
+        if let Some(keep_alive) = &self.keep_alive {
+            // 纯数字为秒数
+            request["keep_alive"] = match keep_alive.parse::<i64>() {
+                Ok(v) => v.into(),
+                Err(_) => keep_alive.as_str().into(),
+            };
+        }
+        // 生成参数优先于 `options` 中的同名项
+        let mut options = self.options.clone();
+        let params = [
+            ("temperature", params.temperature.map(Value::from)),
+            ("top_p", params.top_p.map(Value::from)),
+            ("num_predict", params.max_tokens.map(Value::from)),
+            ("seed", params.seed.map(Value::from)),
+            (
+                "stop",
+                (!params.stop.is_empty()).then(|| Value::from(params.stop.clone())),
+            ),
+        ];
+        for (key, value) in params {
+            if let Some(value) = value {
+                options.insert(key.to_string(), value);
+            }
+        }
+        if !options.is_empty() {
+            request["options"] = Value::Object(options);
+        }
+        if use_tools && !tools.is_empty() {
+            request["tools"] = tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name(),
+                            "description": tool.description(),
+                            "parameters": tool.parameters(),
+                        }
+                    })
+                })
+                .collect();
+        }
+
+        request
+    }
+
+    fn parse_reply(&self, body: Value) -> Result<Reply, RequestError> {
+        let message = body
+            .get("message")
+            .ok_or_else(|| RequestError::Decode("响应中没有 message".to_string()))?;
+
+        let tool_calls: Vec<ToolCall> = message["tool_calls"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .map(|(i, call)| ToolCall {
+                id: call["id"]
+                    .as_str()
+                    .map_or_else(|| format!("call_{}", i), str::to_string),
+                name: call["function"]["name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                arguments: call["function"]["arguments"].to_string(),
+            })
+            .collect();
+
+        let finish = match body["done_reason"].as_str() {
+            Some("length") => FinishReason::Length,
+            _ if !tool_calls.is_empty() => FinishReason::ToolCalls,
+            _ => FinishReason::Stop,
+        };
+        let usage = body["eval_count"].is_u64().then(|| {
+            provider::usage(
+                body["prompt_eval_count"].as_u64().unwrap_or_default() as u32,
+                body["eval_count"].as_u64().unwrap_or_default() as u32,
+            )
+        });
+
+        Ok(Reply {
+            content: message["content"]
+                .as_str()
+                .filter(|v| !v.is_empty())
+                .map(str::to_string),
+            reasoning: message["thinking"]
+                .as_str()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(|text| Reasoning {
+                    text: text.to_string(),
+                    signature: None,
+                    redacted: Vec::new(),
+                }),
+            tool_calls,
+            model: body["model"].as_str().unwrap_or_default().to_string(),
+            usage,
+            finish,
+        })
+    }
+
+    fn embeddings_path(&self) -> Option<&'static str> {
+        Some("/api/embed")
+    }
+
+    fn parse_embeddings(&self, body: Value) -> Result<Vec<Vec<f32>>, RequestError> {
+        serde_json::from_value(body["embeddings"].clone())
+            .map_err(|err| RequestError::Decode(err.to_string()))
+    }
+
+    fn models_path(&self) -> &'static str {
+        "/api/tags"
+    }
+
+    fn parse_models(&self, body: Value) -> Result<Vec<ModelInfo>, RequestError> {
+        let models = body["models"]
+            .as_array()
+            .ok_or_else(|| RequestError::Decode("响应中没有模型列表".to_string()))?;
+        Ok(models
+            .iter()
+            .filter_map(|v| {
+                let details = &v["details"];
+                let detail = [
+                    details["parameter_size"].as_str().map(str::to_string),
+                    details["quantization_level"].as_str().map(str::to_string),
+                    v["size"]
+                        .as_u64()
+                        .map(|size| format!("{:.1} GB", size as f64 / 1e9)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(", ");
+                Some(ModelInfo {
+                    name: v["name"].as_str()?.to_string(),
+                    detail: (!detail.is_empty()).then_some(detail),
+                })
+            })
+            .collect())
+    }
+}
+
+/// 查找工具结果对应的工具名。生成的 id 每轮都从 `call_0` 开始，所以从后往前找
+fn tool_name(msgs: &[Message], tool_call_id: Option<&str>) -> String {
+    msgs.iter()
+        .rev()
+        .flat_map(|msg| msg.tool_calls.iter())
+        .find(|call| Some(call.id.as_str()) == tool_call_id)
+        .map(|call| call.name.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_ollama() {
+    use crate::config::{Config, ProviderKind};
+    use crate::mock::MockServer;
+    use crate::req::ChatClient;
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            json!({
+                "model": "qwen3:8b",
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{ "function": { "name": "echo", "arguments": { "text": "hi" } } }]
+                },
+                "done": true,
+                "done_reason": "stop",
+                "prompt_eval_count": 20,
+                "eval_count": 5
+            }),
+        ),
+        (
+            200,
+            json!({
+                "model": "qwen3:8b",
+                "message": { "role": "assistant", "content": "done" },
+                "done": true,
+                "done_reason": "stop",
+                "prompt_eval_count": 30,
+                "eval_count": 5
+            }),
+        ),
+        (
+            200,
+            json!({
+                "models": [{
+                    "name": "qwen3:8b",
+                    "size": 5_200_000_000u64,
+                    "details": { "parameter_size": "8.2B", "quantization_level": "Q4_K_M" }
+                }]
+            }),
+        ),
+    ])
+    .await;
+
+    let mut config = Config {
+        base_url: Some(server.base_url.trim_end_matches("/v1").to_string()),
+        model_name: Some("qwen3:8b".to_string()),
+        provider: ProviderKind::Ollama,
+        ..Config::default()
+    };
+    config.ollama.keep_alive = Some("10m".to_string());
+    config.ollama.num_ctx = Some(8192);
+    config
+        .ollama
+        .options
+        .insert("temperature".to_string(), json!(0.2));
+    let mut tools = ToolRegistry::new();
+    tools.register(crate::req::EchoTool);
+    let client = ChatClient::new(&config);
+
+    let res = client
+        .request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &tools,
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.content, "done");
+    assert_eq!(res.usage.unwrap().total_tokens, 60);
+
+    let requests = server.requests();
+    assert_eq!(requests[0]["stream"], false);
+    assert_eq!(requests[0]["keep_alive"], "10m");
+    assert_eq!(requests[0]["options"]["num_ctx"], 8192);
+    assert_eq!(requests[0]["options"]["temperature"], 0.2);
+    let msgs = requests[1]["messages"].as_array().unwrap();
+    assert_eq!(
+        msgs[2]["tool_calls"][0]["function"]["arguments"]["text"],
+        "hi"
+    );
+    assert_eq!(msgs[3]["role"], "tool");
+    assert_eq!(msgs[3]["tool_name"], "echo");
+    assert_eq!(msgs[3]["content"], "echo: hi");
+
+    let models = client.list_models().await;
+    let models = models[0].1.as_ref().unwrap();
+    assert_eq!(models[0].name, "qwen3:8b");
+    assert_eq!(models[0].detail.as_deref(), Some("8.2B, Q4_K_M, 5.2 GB"));
+
+    let heads = server.heads();
+    assert_eq!(heads[0].line, "POST /api/chat");
+    assert_eq!(heads[0].header("authorization"), None);
+    assert_eq!(heads[2].line, "GET /api/tags");
+}
@@ -0,0 +1,201 @@
+//! OpenAI 兼容的 `/chat/completions` 接口
+
+use crate::config::GenerationConfig;
+use crate::error::RequestError;
+use crate::provider::{FinishReason, Provider, Reasoning, Reply};
+use crate::req::{Message, Role, ToolCall};
+use crate::tools::ToolRegistry;
+use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
+    ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs, ChatCompletionTool,
+    ChatCompletionToolType, CreateChatCompletionRequestArgs, CreateChatCompletionResponse,
+    FinishReason as OpenAIFinishReason, FunctionCall, FunctionObject, ResponseFormat,
+};
+use kovi::serde_json::{self, Value};
+
+pub struct OpenAI;
+
+impl Provider for OpenAI {
+    fn chat_path(&self) -> &'static str {
+        "/chat/completions"
+    }
+
+    fn embeddings_path(&self) -> Option<&'static str> {
+        Some("/embeddings")
+    }
+
+    fn chat_request(
+        &self,
+        model: &str,
+        system: &str,
+        msgs: &[Message],
+        tools: &ToolRegistry,
+        use_tools: bool,
+        params: &GenerationConfig,
+    ) -> Value {
+        let mut send_msgs: Vec<ChatCompletionRequestMessage> = Vec::with_capacity(msgs.len() + 1);
+
+        send_msgs.push(
+            ChatCompletionRequestSystemMessageArgs::default()
+                .content(system)
+                .build()
+                .unwrap()
+                .into(),
+        );
+
+        for msg in msgs.iter() {
+            match msg.role {
+                Role::System => {
+                    send_msgs.push(
+                        ChatCompletionRequestSystemMessageArgs::default()
+                            .content(msg.content.clone())
+                            .build()
+                            .unwrap()
+                            .into(),
+                    );
+                }
+                Role::User => {
+                    send_msgs.push(
+                        ChatCompletionRequestUserMessageArgs::default()
+                            .content(msg.content.clone())
+                            .build()
+                            .unwrap()
+                            .into(),
+                    );
+                }
+                Role::Assistant => {
+                    let mut args = ChatCompletionRequestAssistantMessageArgs::default();
+                    if !msg.content.is_empty() {
+                        args.content(msg.content.clone());
+                    }
+                    if !msg.tool_calls.is_empty() {
+                        args.tool_calls(
+                            msg.tool_calls
+                                .iter()
+                                .map(|call| ChatCompletionMessageToolCall {
+                                    id: call.id.clone(),
+                                    r#type: ChatCompletionToolType::Function,
+                                    function: FunctionCall {
+                                        name: call.name.clone(),
+                                        arguments: call.arguments.clone(),
+                                    },
+                                })
+                                .collect::<Vec<_>>(),
+                        );
+                    }
+                    send_msgs.push(args.build().unwrap().into());
+                }
+                Role::Tool => {
+                    send_msgs.push(
+                        ChatCompletionRequestToolMessageArgs::default()
+                            .tool_call_id(msg.tool_call_id.clone().unwrap_or_default())
+                            .content(msg.content.clone())
+                            .build()
+                            .unwrap()
+                            .into(),
+                    );
+                }
+            }
+        }
+
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request
+            .model(model)
+            .messages(send_msgs)
+            .response_format(ResponseFormat::Text);
+        if let Some(v) = params.temperature {
+            request.temperature(v);
+        }
+        if let Some(v) = params.top_p {
+            request.top_p(v);
+        }
+        if let Some(v) = params.max_tokens {
+            request.max_tokens(v);
+        }
+        if !params.stop.is_empty() {
+            request.stop(params.stop.clone());
+        }
+        if let Some(v) = params.seed {
+            request.seed(v);
+        }
+
+        if use_tools && !tools.is_empty() {
+            request.tools(
+                tools
+                    .iter()
+                    .map(|tool| ChatCompletionTool {
+                        r#type: ChatCompletionToolType::Function,
+                        function: FunctionObject {
+                            name: tool.name().to_string(),
+                            description: Some(tool.description().to_string()),
+                            parameters: Some(tool.parameters()),
+                            strict: None,
+                        },
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        let mut request = serde_json::to_value(request.build().unwrap()).unwrap();
+        if let Some(effort) = params.reasoning_effort {
+            request["reasoning_effort"] = effort.as_str().into();
+        }
+        request
+    }
+
+    fn parse_reply(&self, body: Value) -> Result<Reply, RequestError> {
+        // DeepSeek 等接口的 reasoning_content 不在 OpenAI 的响应类型中，先从原始响应中取出
+        let reasoning = body["choices"]
+            .as_array()
+            .and_then(|v| v.last())
+            .and_then(|choice| {
+                let message = &choice["message"];
+                message["reasoning_content"]
+                    .as_str()
+                    .or(message["reasoning"].as_str())
+            })
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|text| Reasoning {
+                text: text.to_string(),
+                signature: None,
+                redacted: Vec::new(),
+            });
+
+        let mut response: CreateChatCompletionResponse =
+            serde_json::from_value(body).map_err(|err| RequestError::Decode(err.to_string()))?;
+
+        let Some(choice) = response.choices.pop() else {
+            return Err(RequestError::Decode("响应中没有回答".to_string()));
+        };
+        let finish = match choice.finish_reason {
+            Some(OpenAIFinishReason::Length) => FinishReason::Length,
+            Some(OpenAIFinishReason::ContentFilter) => FinishReason::Refusal,
+            Some(OpenAIFinishReason::ToolCalls | OpenAIFinishReason::FunctionCall) => {
+                FinishReason::ToolCalls
+            }
+            _ if choice.message.refusal.is_some() => FinishReason::Refusal,
+            _ => FinishReason::Stop,
+        };
+
+        Ok(Reply {
+            content: choice.message.content.or(choice.message.refusal),
+            reasoning,
+            tool_calls: choice
+                .message
+                .tool_calls
+                .unwrap_or_default()
+                .into_iter()
+                .map(|call| ToolCall {
+                    id: call.id,
+                    name: call.function.name,
+                    arguments: call.function.arguments,
+                })
+                .collect(),
+            model: response.model,
+            usage: response.usage,
+            finish,
+        })
+    }
+}
@@ -0,0 +1,184 @@
+//! 不同模型接口的请求与响应格式，重试、熔断等由 [`ChatClient`](crate::req::ChatClient) 统一处理
+
+use crate::config::{GenerationConfig, ModelProfile, ProviderKind};
+use crate::error::RequestError;
+use crate::req::{Message, ToolCall};
+use crate::tools::ToolRegistry;
+use async_openai::types::{CompletionUsage, CreateEmbeddingResponse};
+use kovi::serde_json::{self, Value, json};
+use serde::{Deserialize, Serialize};
+
+pub trait Provider: Send + Sync {
+    /// 对话接口的路径，拼接在 `base_url` 之后
+    fn chat_path(&self) -> &'static str;
+
+    /// 添加认证等请求头
+    fn authorize(&self, req: reqwest::RequestBuilder, apikey: &str) -> reqwest::RequestBuilder {
+        if apikey.is_empty() {
+            return req;
+        }
+        req.bearer_auth(apikey)
+    }
+
+    /// 构造请求体。`use_tools` 为 false 时要求模型不再调用工具，直接回答
+    fn chat_request(
+        &self,
+        model: &str,
+        system: &str,
+        msgs: &[Message],
+        tools: &ToolRegistry,
+        use_tools: bool,
+        params: &GenerationConfig,
+    ) -> Value;
+
+    fn parse_reply(&self, body: Value) -> Result<Reply, RequestError>;
+
+    /// 模型列表接口的路径
+    fn models_path(&self) -> &'static str {
+        "/models"
+    }
+
+    /// 解析模型列表，默认为 OpenAI 与 Anthropic 的 `{"data": [{"id": ...}]}`
+    fn parse_models(&self, body: Value) -> Result<Vec<ModelInfo>, RequestError> {
+        let data = body["data"]
+            .as_array()
+            .ok_or_else(|| RequestError::Decode("响应中没有模型列表".to_string()))?;
+        Ok(data
+            .iter()
+            .filter_map(|v| {
+                Some(ModelInfo {
+                    name: v["id"].as_str()?.to_string(),
+                    detail: v["display_name"].as_str().map(str::to_string),
+                })
+            })
+            .collect())
+    }
+
+    /// embedding 接口的路径，不支持 embedding 时为 None
+    fn embeddings_path(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// 构造 embedding 请求体，默认为 OpenAI 格式
+    fn embeddings_request(&self, model: &str, input: &[String]) -> Value {
+        json!({ "model": model, "input": input })
+    }
+
+    /// 解析 embedding，顺序与输入一致，默认为 OpenAI 格式
+    fn parse_embeddings(&self, body: Value) -> Result<Vec<Vec<f32>>, RequestError> {
+        let mut response: CreateEmbeddingResponse =
+            serde_json::from_value(body).map_err(|err| RequestError::Decode(err.to_string()))?;
+        response.data.sort_by_key(|v| v.index);
+        Ok(response.data.into_iter().map(|v| v.embedding).collect())
+    }
+}
+
+pub fn new(profile: &ModelProfile) -> Box<dyn Provider> {
+    match profile.provider {
+        ProviderKind::OpenAI => Box::new(crate::openai::OpenAI),
+        ProviderKind::Anthropic => Box::new(crate::anthropic::Anthropic),
+        ProviderKind::Ollama => Box::new(crate::ollama::Ollama::new(&profile.ollama)),
+    }
+}
+
+/// 接口提供的一个模型
+pub struct ModelInfo {
+    pub name: String,
+    /// 参数量、大小等附加信息
+    pub detail: Option<String>,
+}
+
+/// 单次请求的结果，可能是最终回答，也可能是工具调用
+pub struct Reply {
+    pub content: Option<String>,
+    pub reasoning: Option<Reasoning>,
+    pub tool_calls: Vec<ToolCall>,
+    pub model: String,
+    pub usage: Option<CompletionUsage>,
+    pub finish: FinishReason,
+}
+
+/// 推理模型的思考过程
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Reasoning {
+    pub text: String,
+    /// Anthropic 的思考签名，调用工具后的请求中需要原样带回
+    #[serde(default)]
+    pub signature: Option<String>,
+    /// Anthropic 加密的思考块（`redacted_thinking`）数据，同样需要原样带回
+    #[serde(default)]
+    pub redacted: Vec<String>,
+}
+
+/// 模型停止生成的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    Stop,
+    ToolCalls,
+    /// 达到最大 token 数，回答被截断
+    Length,
+    /// 模型拒绝回答或被内容审核拦截
+    Refusal,
+}
+
+pub fn usage(prompt_tokens: u32, completion_tokens: u32) -> CompletionUsage {
+    CompletionUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+        prompt_tokens_details: None,
+        completion_tokens_details: None,
+    }
+}
+
+/// 拆分回答开头的 `<think>...</think>`，返回思考过程与剩余的回答。
+/// 只处理回答以 `<think>` 或 `</think>` 开头的情况，回答中间提到的标签原样保留
+pub fn split_think(content: &str) -> (Option<String>, String) {
+    let trimmed = content.trim_start();
+    let (think, rest) = if let Some(rest) = trimmed.strip_prefix("</think>") {
+        ("", rest)
+    } else if let Some(body) = trimmed.strip_prefix("<think>")
+        && let Some(v) = body.split_once("</think>")
+    {
+        v
+    } else {
+        return (None, content.to_string());
+    };
+
+    let think = think.trim();
+    (
+        (!think.is_empty()).then(|| think.to_string()),
+        rest.trim_start().to_string(),
+    )
+}
+
+#[test]
+fn test_split_think() {
+    assert_eq!(
+        split_think("<think>\n先算一下\n</think>\n\n答案是 2"),
+        (Some("先算一下".to_string()), "答案是 2".to_string())
+    );
+    assert_eq!(
+        split_think("</think>\n答案是 2"),
+        (None, "答案是 2".to_string())
+    );
+    // 回答中提到标签时不拆分
+    let mention = "推理模型会先输出思考过程，用 </think> 结束后再给出回答";
+    assert_eq!(split_think(mention), (None, mention.to_string()));
+    assert_eq!(
+        split_think("<think>想一想</think>标签 </think> 结束思考"),
+        (
+            Some("想一想".to_string()),
+            "标签 </think> 结束思考".to_string()
+        )
+    );
+    assert_eq!(
+        split_think("<think></think>答案"),
+        (None, "答案".to_string())
+    );
+    assert_eq!(
+        split_think("用 <think> 标签"),
+        (None, "用 <think> 标签".to_string())
+    );
+    assert_eq!(split_think("答案"), (None, "答案".to_string()));
+}
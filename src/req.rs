@@ -1,167 +1,1204 @@
 use crate::*;
-use async_openai::config::OpenAIConfig;
-use async_openai::types::{
-    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestSystemMessageArgs,
-    ChatCompletionRequestUserMessageArgs, ChatCompletionResponseMessage,
-    CreateChatCompletionRequestArgs, ResponseFormat,
-};
-use async_openai::Client;
-use config::START_CHAT;
+use async_openai::types::CompletionUsage;
+use breaker::{CircuitBreaker, State};
+use config::{GenerationConfig, ModelProfile, RequestConfig, START_CHAT};
+use error::RequestError;
+use kovi::serde_json::Value;
+use provider::{FinishReason, ModelInfo, Provider, Reasoning, Reply};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::time::{Duration, Instant};
+use tools::ToolRegistry;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum Role {
     System,
     User,
     Assistant,
+    Tool,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Message {
-    role: Role,
-    content: String,
+    pub(crate) role: Role,
+    pub(crate) content: String,
+    /// 助手消息中模型发起的工具调用
+    #[serde(default)]
+    pub(crate) tool_calls: Vec<ToolCall>,
+    /// 工具消息对应的调用 id
+    #[serde(default)]
+    pub(crate) tool_call_id: Option<String>,
+    /// 助手消息的思考过程
+    #[serde(default)]
+    pub(crate) reasoning: Option<Reasoning>,
 }
 
 impl Message {
     pub fn new(role: Role, content: String) -> Message {
-        Message { role, content }
+        Message {
+            role,
+            content,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            reasoning: None,
+        }
     }
 
     pub fn new_with_user(content: String) -> Message {
         Message::new(Role::User, content)
     }
+
+    pub fn new_with_tool_calls(content: String, tool_calls: Vec<ToolCall>) -> Message {
+        Message {
+            tool_calls,
+            ..Message::new(Role::Assistant, content)
+        }
+    }
+
+    pub fn new_with_tool_result(tool_call_id: String, content: String) -> Message {
+        Message {
+            tool_call_id: Some(tool_call_id),
+            ..Message::new(Role::Tool, content)
+        }
+    }
 }
 
-pub struct ChatClient {
-    client: Client<OpenAIConfig>,
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// JSON 格式的参数
+    pub arguments: String,
+}
+
+pub struct Completion {
+    pub content: String,
+    pub model: String,
+    pub usage: Option<CompletionUsage>,
+    pub latency: Duration,
+    /// 回答过程中是否调用了工具，这样的回答可能依赖当时的状态
+    pub used_tools: bool,
+    /// 给出最终回答的接口名称
+    pub provider: String,
+    /// 推理模型的思考过程，调用工具时为各轮的合并
+    pub reasoning: Option<String>,
+}
+
+/// 请求模型接口的超时与重试策略
+#[derive(Debug, Clone)]
+struct RetryPolicy {
+    /// 一次回答的总时间，包括重试、换用接口与工具调用
+    timeout: Duration,
+    /// 单次请求的超时
+    attempt_timeout: Duration,
+    max_retries: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    fn new(config: &RequestConfig) -> RetryPolicy {
+        RetryPolicy {
+            timeout: Duration::from_secs(config.timeout_secs),
+            attempt_timeout: Duration::from_secs(config.attempt_timeout_secs),
+            max_retries: config.max_retries,
+            backoff: Duration::from_millis(config.backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+        }
+    }
+
+    /// 第 `attempt` 次重试前的等待时间，加上至多 20% 的随机抖动
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|v| v.subsec_nanos())
+            .unwrap_or_default();
+        delay + delay.mul_f64((nanos % 1000) as f64 / 1000.0 * 0.2)
+    }
+}
+
+/// 一个模型接口及其运行状态
+struct Endpoint {
+    name: String,
+    base_url: String,
+    apikey: String,
     model_name: String,
+    provider: Box<dyn Provider>,
+    /// 全局生成参数与接口生成参数合并后的结果
+    generation: GenerationConfig,
+    status: Mutex<EndpointStatus>,
+}
+
+struct EndpointStatus {
+    breaker: CircuitBreaker,
+    /// 成功请求次数
+    served: u64,
+    /// 失败请求次数，重试后成功的不计入
+    failed: u64,
+    last_error: Option<String>,
+}
+
+impl Endpoint {
+    fn new(profile: &ModelProfile, config: &Config) -> Endpoint {
+        Endpoint {
+            name: profile.display_name(),
+            base_url: profile.base_url.clone(),
+            apikey: profile.apikey.clone().unwrap_or_default(),
+            model_name: profile.model_name.clone(),
+            provider: provider::new(profile),
+            generation: config.generation.merge(&profile.generation),
+            status: Mutex::new(EndpointStatus {
+                breaker: CircuitBreaker::new(
+                    config.request.breaker_failures,
+                    Duration::from_secs(config.request.breaker_cooldown_secs),
+                ),
+                served: 0,
+                failed: 0,
+                last_error: None,
+            }),
+        }
+    }
+
+    fn record_success(&self) {
+        let mut status = self.status.lock();
+        status.breaker.record_success();
+        status.served += 1;
+    }
+
+    fn acquire(&self) -> bool {
+        self.status.lock().breaker.acquire()
+    }
+
+    fn release(&self) {
+        self.status.lock().breaker.release();
+    }
+
+    fn record_failure(&self, err: &RequestError) {
+        let mut status = self.status.lock();
+        status.breaker.record_failure();
+        status.failed += 1;
+        status.last_error = Some(err.to_string());
+        if status.breaker.state() == State::Open {
+            log::warn!(
+                "aiqa: Endpoint {} is open after {} failures",
+                self.name,
+                status.breaker.failures()
+            );
+        }
+    }
+}
+
+pub struct ChatClient {
+    http: reqwest::Client,
+    /// 按顺序尝试的接口，第一个为主接口
+    endpoints: Vec<Endpoint>,
+    /// 最近一次回答所用的接口
+    last_provider: Mutex<Option<String>>,
+    max_tool_iterations: usize,
+    retry: RetryPolicy,
 }
 
 impl ChatClient {
     pub fn new(config_: &Config) -> ChatClient {
-        let config = OpenAIConfig::new()
-            .with_api_base(config_.base_url.clone().unwrap())
-            .with_api_key(config_.apikey.clone().unwrap());
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(config_.request.connect_timeout_secs))
+            .build()
+            .unwrap();
 
         ChatClient {
-            client: async_openai::Client::with_config(config),
-            model_name: config_.model_name.clone().unwrap(),
+            http,
+            endpoints: config_
+                .profiles()
+                .iter()
+                .map(|profile| Endpoint::new(profile, config_))
+                .collect(),
+            last_provider: Mutex::new(None),
+            max_tool_iterations: config_.tools.max_iterations,
+            retry: RetryPolicy::new(&config_.request),
+        }
+    }
+
+    /// 各接口的熔断状态与请求统计，用于 `%status` 命令
+    pub fn status(&self) -> String {
+        let mut lines = vec!["模型接口状态".to_string()];
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            let status = endpoint.status.lock();
+            let state = match status.breaker.state() {
+                State::Closed => "正常".to_string(),
+                State::Open => format!(
+                    "熔断中，{} 秒后重试",
+                    status.breaker.remaining().unwrap_or_default().as_secs()
+                ),
+                State::HalfOpen => "等待试探".to_string(),
+            };
+            let mut line = format!(
+                "{}. {}：{}，成功 {} 次，失败 {} 次",
+                i + 1,
+                endpoint.name,
+                state,
+                status.served,
+                status.failed
+            );
+            if status.breaker.failures() > 0
+                && let Some(err) = &status.last_error
+            {
+                line.push_str(&format!("，最近错误：{}", truncate_chars(err, 100)));
+            }
+            lines.push(line);
+        }
+        if let Some(provider) = self.last_provider.lock().as_ref() {
+            lines.push(format!("最近一次回答来自 {}", provider));
+        }
+        lines.join("\n")
+    }
+
+    /// 查询各接口提供的模型，用于 `%models` 命令
+    pub async fn list_models(&self) -> Vec<(String, Result<Vec<ModelInfo>, RequestError>)> {
+        let mut res = Vec::with_capacity(self.endpoints.len());
+        for endpoint in self.endpoints.iter() {
+            let models = self
+                .get::<Value>(endpoint, endpoint.provider.models_path(), self.deadline())
+                .await
+                .and_then(|body| endpoint.provider.parse_models(body));
+            res.push((endpoint.name.clone(), models));
         }
+        res
     }
 
+    /// 依次尝试的接口。熔断中的接口被跳过，全部熔断时仍按顺序尝试，
+    /// 已有请求在试探的接口除外
+    fn candidates(&self) -> Vec<&Endpoint> {
+        let available: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.status.lock().breaker.allows())
+            .collect();
+        if available.is_empty() {
+            return self.endpoints.iter().collect();
+        }
+        available
+    }
+
+    /// 从现在开始计算的总超时截止时间。一次回答或一次总结只计算一次，
+    /// 其中的重试、换用接口、工具调用与分段请求共用这段时间
+    pub fn deadline(&self) -> Instant {
+        Instant::now() + self.retry.timeout
+    }
+
+    async fn post<T: Serialize, R: DeserializeOwned>(
+        &self,
+        endpoint: &Endpoint,
+        path: &str,
+        body: &T,
+        deadline: Instant,
+    ) -> Result<R, RequestError> {
+        self.request(endpoint, path, Some(body), deadline).await
+    }
+
+    async fn get<R: DeserializeOwned>(
+        &self,
+        endpoint: &Endpoint,
+        path: &str,
+        deadline: Instant,
+    ) -> Result<R, RequestError> {
+        self.request::<(), R>(endpoint, path, None, deadline).await
+    }
+
+    /// 发送请求，有请求体时为 POST，否则为 GET。
+    /// 超时、限流、服务端错误与网络错误时按重试策略重试，直到 `deadline`
+    async fn request<T: Serialize, R: DeserializeOwned>(
+        &self,
+        endpoint: &Endpoint,
+        path: &str,
+        body: Option<&T>,
+        deadline: Instant,
+    ) -> Result<R, RequestError> {
+        let url = format!("{}{}", endpoint.base_url.trim_end_matches('/'), path);
+
+        let mut attempt = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RequestError::Deadline);
+            }
+            let capped = remaining < self.retry.attempt_timeout;
+            let err = match self
+                .send(
+                    endpoint,
+                    &url,
+                    body,
+                    self.retry.attempt_timeout.min(remaining),
+                )
+                .await
+            {
+                Ok(v) => return Ok(v),
+                // 因为总时间不足而超时，不算接口的问题
+                Err(RequestError::Timeout) if capped => return Err(RequestError::Deadline),
+                Err(err) => err,
+            };
+
+            if !err.retryable() || attempt >= self.retry.max_retries {
+                return Err(err);
+            }
+            let delay = match &err {
+                RequestError::RateLimited {
+                    retry_after: Some(v),
+                    ..
+                } => *v,
+                _ => self.retry.backoff_delay(attempt),
+            };
+            // 等待后已经没有时间再请求一次
+            if Instant::now() + delay >= deadline {
+                return Err(err);
+            }
+
+            log::warn!(
+                "aiqa: Request to {} failed, retry in {:?}: {}",
+                endpoint.name,
+                delay,
+                err
+            );
+            kovi::tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send<T: Serialize, R: DeserializeOwned>(
+        &self,
+        endpoint: &Endpoint,
+        url: &str,
+        body: Option<&T>,
+        timeout: Duration,
+    ) -> Result<R, RequestError> {
+        let req = match body {
+            Some(body) => self.http.post(url).json(body),
+            None => self.http.get(url),
+        };
+        let res = endpoint
+            .provider
+            .authorize(req, &endpoint.apikey)
+            .timeout(timeout)
+            .send()
+            .await
+            .map_err(RequestError::from_reqwest)?;
+
+        let status = res.status().as_u16();
+        if res.status().is_success() {
+            let bytes = res.bytes().await.map_err(RequestError::from_reqwest)?;
+            return kovi::serde_json::from_slice(&bytes)
+                .map_err(|err| RequestError::Decode(err.to_string()));
+        }
+
+        let retry_after = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let message = error_message(&res.text().await.unwrap_or_default());
+
+        Err(match status {
+            401 | 403 => RequestError::Auth(message),
+            429 => RequestError::RateLimited {
+                retry_after,
+                message,
+            },
+            500.. => RequestError::Server(status, message),
+            _ => RequestError::Api(status, message),
+        })
+    }
+
+    /// 请求回答。模型调用工具时执行工具并把结果交给模型继续请求，
+    /// 直到得到最终回答；超过 `max_tool_iterations` 轮后不再提供工具，要求模型直接回答
+    ///
+    /// `params` 覆盖配置中的生成参数
     pub async fn request_chat_completion(
         &self,
         msgs: Vec<Message>,
-    ) -> Result<ChatCompletionResponseMessage, Box<dyn Error>> {
-        let mut send_msgs = Vec::with_capacity(msgs.len() + 1);
-
-        send_msgs.push(
-            ChatCompletionRequestSystemMessageArgs::default()
-                .content(START_CHAT)
-                .build()
-                .unwrap()
-                .into(),
-        );
-
-        for msg in msgs.iter() {
-            match msg.role {
-                Role::System => {
-                    send_msgs.push(
-                        ChatCompletionRequestSystemMessageArgs::default()
-                            .content(msg.content.clone())
-                            .build()
-                            .unwrap()
-                            .into(),
-                    );
+        tools: &ToolRegistry,
+        params: &GenerationConfig,
+    ) -> Result<Completion, Box<dyn Error>> {
+        self.request_with_params(START_CHAT, msgs, tools, params, self.deadline())
+            .await
+    }
+
+    /// 使用指定的系统提示词代替默认的问答提示词请求回答，在 `deadline` 前完成
+    pub async fn request_with_system(
+        &self,
+        system: &str,
+        msgs: Vec<Message>,
+        tools: &ToolRegistry,
+        deadline: Instant,
+    ) -> Result<Completion, Box<dyn Error>> {
+        self.request_with_params(system, msgs, tools, &GenerationConfig::default(), deadline)
+            .await
+    }
+
+    async fn request_with_params(
+        &self,
+        system: &str,
+        mut msgs: Vec<Message>,
+        tools: &ToolRegistry,
+        params: &GenerationConfig,
+        deadline: Instant,
+    ) -> Result<Completion, Box<dyn Error>> {
+        let start = Instant::now();
+        let mut usage: Option<CompletionUsage> = None;
+        let mut used_tools = false;
+        let mut reasoning: Vec<String> = Vec::new();
+
+        for iteration in 0.. {
+            let use_tools = !tools.is_empty() && iteration < self.max_tool_iterations;
+            let (mut reply, provider) = self
+                .create(system, &msgs, tools, use_tools, params, deadline)
+                .await?;
+
+            if reply.reasoning.is_none()
+                && let Some(content) = &reply.content
+            {
+                let (text, rest) = provider::split_think(content);
+                reply.reasoning = text.map(|text| Reasoning {
+                    text,
+                    signature: None,
+                    redacted: Vec::new(),
+                });
+                reply.content = Some(rest);
+            }
+            reasoning.extend(
+                reply
+                    .reasoning
+                    .as_ref()
+                    .filter(|v| !v.text.is_empty())
+                    .map(|v| v.text.clone()),
+            );
+
+            add_usage(&mut usage, reply.usage);
+            if reply.finish == FinishReason::Length {
+                log::warn!("aiqa: Answer from {} was truncated", provider);
+            }
+            if reply.finish == FinishReason::Refusal && reply.content.is_none() {
+                return Err("模型拒绝回答".into());
+            }
+
+            if !use_tools || reply.tool_calls.is_empty() {
+                log::info!("aiqa: Answered by {}", provider);
+                *self.last_provider.lock() = Some(provider.to_string());
+                return Ok(Completion {
+                    content: reply.content.ok_or("no content")?,
+                    model: reply.model,
+                    usage,
+                    latency: start.elapsed(),
+                    used_tools,
+                    provider: provider.to_string(),
+                    reasoning: (!reasoning.is_empty()).then(|| reasoning.join("\n\n")),
+                });
+            }
+
+            log::info!(
+                "aiqa: tool calls: {}",
+                reply
+                    .tool_calls
+                    .iter()
+                    .map(|call| call.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+
+            used_tools = true;
+            let calls = reply.tool_calls.clone();
+            msgs.push(Message {
+                reasoning: reply.reasoning,
+                ..Message::new_with_tool_calls(reply.content.unwrap_or_default(), reply.tool_calls)
+            });
+            for call in calls {
+                // 工具同样受总超时限制
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let result = kovi::tokio::time::timeout(remaining, tools.call(&call))
+                    .await
+                    .map_err(|_| {
+                        log::warn!("aiqa: Tool {} timed out", call.name);
+                        RequestError::Deadline
+                    })?;
+                msgs.push(Message::new_with_tool_result(call.id, result));
+            }
+        }
+
+        unreachable!()
+    }
+
+    /// 第一个支持 embedding 的接口。只使用这一个接口，其他接口的 embedding 模型不一定相同
+    fn embedding_endpoint(&self) -> Option<&Endpoint> {
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.provider.embeddings_path().is_some())
+    }
+
+    /// 是否有支持 embedding 的接口，没有时不能使用知识库与回答缓存的 embedding
+    pub fn supports_embeddings(&self) -> bool {
+        self.embedding_endpoint().is_some()
+    }
+
+    /// 计算一组文本的 embedding，顺序与输入一致
+    pub async fn embed(
+        &self,
+        model: &str,
+        input: Vec<String>,
+    ) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let endpoint = self
+            .embedding_endpoint()
+            .ok_or("没有支持 embedding 的模型接口")?;
+        let path = endpoint.provider.embeddings_path().unwrap_or_default();
+        let request = endpoint.provider.embeddings_request(model, &input);
+
+        let body = self
+            .post::<_, Value>(endpoint, path, &request, self.deadline())
+            .await?;
+        Ok(endpoint.provider.parse_embeddings(body)?)
+    }
+
+    async fn create(
+        &self,
+        system: &str,
+        msgs: &[Message],
+        tools: &ToolRegistry,
+        use_tools: bool,
+        params: &GenerationConfig,
+        deadline: Instant,
+    ) -> Result<(Reply, &str), Box<dyn Error>> {
+        let mut last_err = None;
+        for endpoint in self.candidates() {
+            // 总时间用完后不再换用接口，也不计入后面接口的熔断
+            if Instant::now() >= deadline {
+                last_err.get_or_insert(RequestError::Deadline);
+                break;
+            }
+            if !endpoint.acquire() {
+                continue;
+            }
+            let request = endpoint.provider.chat_request(
+                &endpoint.model_name,
+                system,
+                msgs,
+                tools,
+                use_tools,
+                &endpoint.generation.merge(params),
+            );
+
+            let res = self
+                .post::<_, Value>(endpoint, endpoint.provider.chat_path(), &request, deadline)
+                .await
+                .and_then(|body| endpoint.provider.parse_reply(body));
+            match res {
+                Ok(reply) => {
+                    endpoint.record_success();
+                    return Ok((reply, &endpoint.name));
                 }
-                Role::User => {
-                    send_msgs.push(
-                        ChatCompletionRequestUserMessageArgs::default()
-                            .content(msg.content.clone())
-                            .build()
-                            .unwrap()
-                            .into(),
-                    );
+                Err(err) if err.failover() => {
+                    log::warn!("aiqa: Endpoint {} failed: {}", endpoint.name, err);
+                    endpoint.record_failure(&err);
+                    last_err = Some(err);
                 }
-                Role::Assistant => {
-                    send_msgs.push(
-                        ChatCompletionRequestAssistantMessageArgs::default()
-                            .content(msg.content.clone())
-                            .build()
-                            .unwrap()
-                            .into(),
-                    );
+                Err(err) => {
+                    endpoint.release();
+                    return Err(err.into());
                 }
             }
         }
 
-        let request = CreateChatCompletionRequestArgs::default()
-            // .max_tokens(MOBEL_MAX_TOKEN)
-            .model(self.model_name.clone())
-            .messages(send_msgs)
-            .response_format(ResponseFormat::Text)
-            .build()
-            .unwrap();
+        Err(last_err.map_or("没有可用的模型接口".into(), |err| err.into()))
+    }
+}
 
-        let choice = {
-            let mut response = self.client.chat().create(request).await?;
-            response.choices.pop()
-        };
+/// 累加多次请求的 token 用量
+pub fn add_usage(total: &mut Option<CompletionUsage>, usage: Option<CompletionUsage>) {
+    let Some(v) = usage else {
+        return;
+    };
+    let total = total.get_or_insert(CompletionUsage {
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        total_tokens: 0,
+        prompt_tokens_details: None,
+        completion_tokens_details: None,
+    });
+    total.prompt_tokens += v.prompt_tokens;
+    total.completion_tokens += v.completion_tokens;
+    total.total_tokens += v.total_tokens;
+}
+
+/// `Retry-After` 可以是秒数或 HTTP 日期
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).ok();
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+/// 从错误响应中取出错误信息，OpenAI 格式为 `{"error": {"message": ...}}`
+fn error_message(body: &str) -> String {
+    let json: Option<kovi::serde_json::Value> = kovi::serde_json::from_str(body).ok();
+    let message = json.as_ref().and_then(|v| {
+        v["error"]["message"]
+            .as_str()
+            .or(v["error"].as_str())
+            .or(v["message"].as_str())
+    });
+    match message {
+        Some(v) => v.to_string(),
+        None => truncate_chars(body.trim(), 200),
+    }
+}
+
+#[cfg(test)]
+pub(crate) struct EchoTool;
+
+#[cfg(test)]
+impl crate::tools::Tool for EchoTool {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn description(&self) -> &str {
+        "echo the text"
+    }
+
+    fn parameters(&self) -> kovi::serde_json::Value {
+        kovi::serde_json::json!({
+            "type": "object",
+            "properties": { "text": { "type": "string" } },
+            "required": ["text"]
+        })
+    }
+
+    fn execute(
+        &self,
+        args: kovi::serde_json::Value,
+    ) -> kovi::futures_util::future::BoxFuture<'_, Result<String, String>> {
+        Box::pin(async move {
+            args["text"]
+                .as_str()
+                .map(|v| format!("echo: {}", v))
+                .ok_or("missing text".to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_tool_calls() {
+    use crate::mock::{self, MockServer};
+    use kovi::serde_json::json;
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            mock::tool_calls(&[
+                ("call_1", "echo", json!({"text": "hi"})),
+                ("call_2", "missing", json!({})),
+            ]),
+        ),
+        (200, mock::answer("done")),
+    ])
+    .await;
+
+    let mut tools = ToolRegistry::new();
+    tools.register(EchoTool);
+
+    let res = mock::client(&server.base_url)
+        .request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &tools,
+            &GenerationConfig::default(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.content, "done");
+    assert_eq!(res.usage.unwrap().total_tokens, 30);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0]["tools"][0]["function"]["name"], "echo");
+
+    let msgs = requests[1]["messages"].as_array().unwrap();
+    assert_eq!(msgs[2]["tool_calls"][0]["id"], "call_1");
+    assert_eq!(msgs[3]["role"], "tool");
+    assert_eq!(msgs[3]["tool_call_id"], "call_1");
+    assert_eq!(msgs[3]["content"], "echo: hi");
+    assert_eq!(msgs[4]["content"], "error: unknown tool `missing`");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_tool_iteration_limit() {
+    use crate::mock::{self, MockServer};
+    use kovi::serde_json::json;
+
+    let call = || mock::tool_calls(&[("call", "echo", json!({"text": "again"}))]);
+    let server = MockServer::start(vec![(200, call()), (200, mock::answer("final"))]).await;
+
+    let mut tools = ToolRegistry::new();
+    tools.register(EchoTool);
 
-        match choice {
-            Some(v) => Ok(v.message),
-            None => Err("请求失败".into()),
+    let mut config = mock::config(&server.base_url);
+    config.tools.max_iterations = 1;
+    let res = ChatClient::new(&config)
+        .request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &tools,
+            &GenerationConfig::default(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.content, "final");
+
+    // 达到上限后的请求不再提供工具
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].get("tools").is_none());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_shared_deadline() {
+    use crate::mock::{self, MockServer, Response};
+    use kovi::serde_json::json;
+
+    // 每次请求都没有超过总超时，但工具调用的两轮加起来超过了
+    let delay = Duration::from_millis(300);
+    let server = MockServer::start(vec![
+        Response::from((
+            200,
+            mock::tool_calls(&[("call", "echo", json!({"text": "again"}))]),
+        ))
+        .delay(delay),
+        Response::from((200, mock::answer("final"))).delay(delay),
+    ])
+    .await;
+    let mut client = mock::client(&server.base_url);
+    client.retry.timeout = Duration::from_millis(500);
+    client.retry.max_retries = 0;
+
+    let mut tools = ToolRegistry::new();
+    tools.register(EchoTool);
+
+    let err = client
+        .request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &tools,
+            &GenerationConfig::default(),
+        )
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.to_string(), RequestError::Deadline.to_string());
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_slow_tool() {
+    use crate::mock::{self, MockServer};
+    use kovi::futures_util::future::BoxFuture;
+    use kovi::serde_json::json;
+
+    struct SlowTool;
+
+    impl crate::tools::Tool for SlowTool {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn description(&self) -> &str {
+            "never finishes in time"
+        }
+
+        fn parameters(&self) -> Value {
+            json!({ "type": "object", "properties": {} })
         }
+
+        fn execute(&self, _args: Value) -> BoxFuture<'_, Result<String, String>> {
+            Box::pin(async {
+                kovi::tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok("done".to_string())
+            })
+        }
+    }
+
+    let server = MockServer::start(vec![
+        (200, mock::tool_calls(&[("call", "slow", json!({}))])),
+        (200, mock::answer("final")),
+    ])
+    .await;
+    let mut client = mock::client(&server.base_url);
+    client.retry.timeout = Duration::from_millis(300);
+
+    let mut tools = ToolRegistry::new();
+    tools.register(SlowTool);
+
+    let start = Instant::now();
+    let err = client
+        .request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &tools,
+            &GenerationConfig::default(),
+        )
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.to_string(), RequestError::Deadline.to_string());
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_retry() {
+    use crate::mock::{self, MockServer, Response};
+    use kovi::serde_json::json;
+
+    let server = MockServer::start(vec![
+        Response::from((500, json!({"error": {"message": "overloaded"}}))),
+        Response::from((429, json!({"error": {"message": "slow down"}})))
+            .header("retry-after", "0"),
+        Response::from((200, mock::answer("ok"))),
+    ])
+    .await;
+    let mut client = mock::client(&server.base_url);
+    client.retry.backoff = Duration::from_millis(10);
+
+    let res = client
+        .request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &ToolRegistry::new(),
+            &GenerationConfig::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.content, "ok");
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_request_errors() {
+    use crate::mock::{self, MockServer, Response};
+    use kovi::serde_json::json;
+
+    // 认证失败不重试
+    let server = MockServer::start(vec![
+        (401, json!({"error": {"message": "invalid api key"}})),
+        (401, json!({"error": {"message": "invalid api key"}})),
+    ])
+    .await;
+    let client = mock::client(&server.base_url);
+    let err = client
+        .post::<_, Value>(
+            &client.endpoints[0],
+            "/chat/completions",
+            &json!({}),
+            client.deadline(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::Auth(ref v) if v == "invalid api key"));
+    assert_eq!(server.requests().len(), 1);
+
+    // 单次超时后重试，仍然超时则返回超时错误
+    let slow = || Response::from((200, json!({}))).delay(Duration::from_millis(500));
+    let server = MockServer::start(vec![slow(), slow()]).await;
+    let mut client = mock::client(&server.base_url);
+    client.retry.attempt_timeout = Duration::from_millis(100);
+    client.retry.max_retries = 1;
+    client.retry.backoff = Duration::from_millis(10);
+    let err = client
+        .post::<_, Value>(
+            &client.endpoints[0],
+            "/chat/completions",
+            &json!({}),
+            client.deadline(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::Timeout));
+    assert_eq!(server.requests().len(), 2);
+
+    // 限流要求的等待时间超过总超时时不再重试
+    let server = MockServer::start(vec![
+        Response::from((429, json!({"error": "rate limited"}))).header("retry-after", "600"),
+    ])
+    .await;
+    let client = mock::client(&server.base_url);
+    let err = client
+        .post::<_, Value>(
+            &client.endpoints[0],
+            "/chat/completions",
+            &json!({}),
+            client.deadline(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::RateLimited { retry_after: Some(v), .. } if v == Duration::from_secs(600)
+    ));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_failover() {
+    use crate::config::ProviderKind;
+    use crate::mock::{self, MockServer};
+    use kovi::serde_json::json;
+
+    let down = || (500, json!({"error": {"message": "down"}}));
+    let primary = MockServer::start(vec![down(), down()]).await;
+    let backup = MockServer::start(vec![
+        (200, mock::answer("from backup")),
+        (200, mock::answer("again")),
+    ])
+    .await;
+
+    let mut config = Config {
+        fallbacks: vec![ModelProfile {
+            name: Some("backup".to_string()),
+            base_url: backup.base_url.clone(),
+            apikey: None,
+            model_name: "backup-model".to_string(),
+            provider: ProviderKind::OpenAI,
+            ollama: Default::default(),
+            generation: Default::default(),
+        }],
+        ..mock::config(&primary.base_url)
+    };
+    config.request.max_retries = 0;
+    config.request.breaker_failures = 1;
+    let client = ChatClient::new(&config);
+
+    let tools = ToolRegistry::new();
+    let params = GenerationConfig::default();
+    let ask = || {
+        client.request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &tools,
+            &params,
+        )
+    };
+    let res = ask().await.unwrap();
+    assert_eq!(res.content, "from backup");
+    assert_eq!(res.provider, "backup");
+    assert_eq!(backup.requests()[0]["model"], "backup-model");
+
+    // 主接口已熔断，直接请求备用接口
+    let res = ask().await.unwrap();
+    assert_eq!(res.content, "again");
+    assert_eq!(primary.requests().len(), 1);
+    assert!(client.status().contains("熔断中"));
+
+    // 请求本身有误时直接返回，不计入熔断也不换接口
+    let primary = MockServer::start(vec![(400, json!({"error": {"message": "bad"}}))]).await;
+    let backup = MockServer::start(vec![(200, mock::answer("unused"))]).await;
+    config.base_url = Some(primary.base_url.clone());
+    config.fallbacks[0].base_url = backup.base_url.clone();
+    let client = ChatClient::new(&config);
+    let Err(err) = client
+        .request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &tools,
+            &params,
+        )
+        .await
+    else {
+        panic!("should fail");
+    };
+    assert!(err.to_string().contains("bad"));
+    assert!(backup.requests().is_empty());
+    assert!(!client.status().contains("熔断中"));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_failover_errors() {
+    use crate::config::ProviderKind;
+    use crate::mock::{self, MockServer, Response};
+    use kovi::serde_json::json;
+
+    let client = |primary: &MockServer, backup: &MockServer| {
+        let mut config = Config {
+            fallbacks: vec![ModelProfile {
+                name: Some("backup".to_string()),
+                base_url: backup.base_url.clone(),
+                apikey: None,
+                model_name: "backup-model".to_string(),
+                provider: ProviderKind::OpenAI,
+                ollama: Default::default(),
+                generation: Default::default(),
+            }],
+            ..mock::config(&primary.base_url)
+        };
+        config.request.max_retries = 0;
+        config.request.breaker_failures = 1;
+        ChatClient::new(&config)
+    };
+    async fn ask(client: &ChatClient) -> Result<Completion, Box<dyn Error>> {
+        client
+            .request_chat_completion(
+                vec![Message::new_with_user("hi".to_string())],
+                &ToolRegistry::new(),
+                &GenerationConfig::default(),
+            )
+            .await
+    }
+
+    // 找不到模型与无法解析的响应换用备用接口
+    for primary in [
+        Response::from((404, json!({"error": {"message": "model not found"}}))),
+        Response::from((200, json!({"unexpected": true}))),
+    ] {
+        let primary = MockServer::start(vec![primary]).await;
+        let backup = MockServer::start(vec![(200, mock::answer("from backup"))]).await;
+        let client = client(&primary, &backup);
+        let res = ask(&client).await.unwrap();
+        assert_eq!(res.content, "from backup");
+        assert!(client.status().contains("熔断中"));
     }
+
+    // 总时间用完导致的超时不计入熔断
+    let primary = MockServer::start(vec![
+        Response::from((200, mock::answer("slow"))).delay(Duration::from_millis(500)),
+    ])
+    .await;
+    let backup = MockServer::start(vec![(200, mock::answer("unused"))]).await;
+    let mut client = client(&primary, &backup);
+    client.retry.timeout = Duration::from_millis(200);
+    let err = ask(&client).await.err().unwrap();
+    assert_eq!(err.to_string(), RequestError::Deadline.to_string());
+    assert!(backup.requests().is_empty());
+    assert!(!client.status().contains("熔断中"));
+    assert!(client.status().contains("失败 0 次"));
 }
 
-// pub(crate) async fn request_chat_completion(
-//     messages: Vec<AiMessage>,
-// ) -> Result<ApiResponse, Box<dyn Error>> {
-//     let timeout_duration = Duration::from_secs(30);
+#[cfg(test)]
+#[tokio::test]
+async fn test_embedding_endpoint() {
+    use crate::config::ProviderKind;
+    use crate::mock::MockServer;
+    use kovi::serde_json::json;
+
+    let primary = MockServer::start(Vec::<(u16, Value)>::new()).await;
+    let ollama = MockServer::start(vec![(
+        200,
+        json!({ "model": "bge-m3", "embeddings": [[0.1, 0.2], [0.3, 0.4]] }),
+    )])
+    .await;
 
-//     let client = Client::builder().timeout(timeout_duration).build()?;
+    // Anthropic 没有 embedding，使用第一个支持的备用接口
+    let mut config = Config {
+        apikey: Some("sk-ant-test".to_string()),
+        base_url: Some(primary.base_url.clone()),
+        model_name: Some("claude-mock".to_string()),
+        provider: ProviderKind::Anthropic,
+        ..Config::default()
+    };
+    let client = ChatClient::new(&config);
+    assert!(!client.supports_embeddings());
+    assert!(client.embed("bge-m3", vec!["a".to_string()]).await.is_err());
 
-//     let chat_request = ChatRequest {
-//         model: "glm-4-plus".to_string(),
-//         messages,
-//     };
+    config.fallbacks.push(ModelProfile {
+        name: None,
+        base_url: ollama.base_url.trim_end_matches("/v1").to_string(),
+        apikey: None,
+        model_name: "qwen3:8b".to_string(),
+        provider: ProviderKind::Ollama,
+        ollama: Default::default(),
+        generation: Default::default(),
+    });
+    let client = ChatClient::new(&config);
+    assert!(client.supports_embeddings());
+    let res = client
+        .embed("bge-m3", vec!["a".to_string(), "b".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(res, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
 
-//     let response_result = timeout(timeout_duration, async {
-//         let response = client
-//             .post("https://open.bigmodel.cn/api/paas/v4/chat/completions")
-//             .header("Authorization", format!("Bearer {}", APIKEY))
-//             .header("Content-Type", "application/json")
-//             .json(&chat_request)
-//             .send()
-//             .await?;
+    assert!(primary.requests().is_empty());
+    assert_eq!(ollama.heads()[0].line, "POST /api/embed");
+    assert_eq!(ollama.requests()[0]["input"], json!(["a", "b"]));
+}
 
-//         let chat_response = response.json::<ApiResponse>().await?;
+#[cfg(test)]
+#[tokio::test]
+async fn test_generation_params() {
+    use crate::mock::{self, MockServer};
 
-//         Ok(chat_response)
-//     })
-//     .await;
+    let server = MockServer::start(vec![(200, mock::answer("ok"))]).await;
+    let mut config = mock::config(&server.base_url);
+    config.generation.temperature = Some(1.0);
+    config.generation.max_tokens = Some(1000);
 
-//     // 检查是否超时
-//     match response_result {
-//         Ok(result) => result,
-//         Err(_) => Err("请求超时".into()),
-//     }
-// }
+    let params = GenerationConfig {
+        temperature: Some(0.5),
+        stop: vec!["END".to_string()],
+        ..GenerationConfig::default()
+    };
+    ChatClient::new(&config)
+        .request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &ToolRegistry::new(),
+            &params,
+        )
+        .await
+        .unwrap();
 
-// #[tokio::test]
-// async fn ai_chat() {
-//     let config = crate::config::Config
+    let request = &server.requests()[0];
+    assert_eq!(request["temperature"], 0.5);
+    assert_eq!(request["max_tokens"], 1000);
+    assert_eq!(request["stop"][0], "END");
+    assert!(request.get("seed").is_none());
+}
 
-//     let client = ChatClient::new();
+#[cfg(test)]
+#[tokio::test]
+async fn test_reasoning() {
+    use crate::mock::{self, MockServer};
 
-//     let msgs = vec![Message {
-//         role: Role::User,
-//         content: "我有一个群聊机器人的一个插件，可以请求ai进行回答，曾经我命名为“gpt”，现在我希望可以让这个名字更加通用，有什么推荐的名字吗".to_string(),
-//     }];
+    let mut with_field = mock::answer("答案是 2");
+    with_field["choices"][0]["message"]["reasoning_content"] = "1 加 1".into();
+    let server = MockServer::start(vec![
+        (200, with_field),
+        (200, mock::answer("<think>\n先想想\n</think>\n\n答案是 3")),
+    ])
+    .await;
+    let client = mock::client(&server.base_url);
+    let tools = ToolRegistry::new();
+    let params = GenerationConfig {
+        reasoning_effort: Some(config::ReasoningEffort::High),
+        ..GenerationConfig::default()
+    };
+    let ask = || {
+        client.request_chat_completion(
+            vec![Message::new_with_user("hi".to_string())],
+            &tools,
+            &params,
+        )
+    };
 
-//     let response = client.request_chat_completion(msgs).await.unwrap();
+    let res = ask().await.unwrap();
+    assert_eq!(res.content, "答案是 2");
+    assert_eq!(res.reasoning.as_deref(), Some("1 加 1"));
+    assert_eq!(server.requests()[0]["reasoning_effort"], "high");
 
-//     println!("{:?}", response);
-// }
+    let res = ask().await.unwrap();
+    assert_eq!(res.content, "答案是 3");
+    assert_eq!(res.reasoning.as_deref(), Some("先想想"));
+}
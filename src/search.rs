@@ -0,0 +1,349 @@
+//! 网页搜索工具，搜索结果以脚注的形式在回答中引用
+
+use crate::config::{JsonSearchConfig, SearchConfig, SearchProviderKind};
+use crate::tools::Tool;
+use kovi::futures_util::future::BoxFuture;
+use kovi::serde_json::{Value, json};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+/// 搜索服务
+pub trait SearchProvider: Send + Sync {
+    fn search<'a>(
+        &'a self,
+        query: &'a str,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Vec<SearchResult>, String>>;
+}
+
+/// 根据配置创建搜索服务，未开启或没有配置地址时返回 None
+pub fn new(config: &SearchConfig) -> Option<Arc<dyn SearchProvider>> {
+    if !config.enabled || config.url.is_empty() {
+        return None;
+    }
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()
+        .ok()?;
+    Some(match config.provider {
+        SearchProviderKind::SearXNG => Arc::new(SearXNG {
+            http,
+            url: config.url.clone(),
+        }),
+        SearchProviderKind::Json => Arc::new(JsonApi {
+            http,
+            url: config.url.clone(),
+            apikey: config.apikey.clone(),
+            config: config.json.clone(),
+        }),
+    })
+}
+
+pub struct SearXNG {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl SearchProvider for SearXNG {
+    fn search<'a>(
+        &'a self,
+        query: &'a str,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Vec<SearchResult>, String>> {
+        Box::pin(async move {
+            let url = format!("{}/search", self.url.trim_end_matches('/'));
+            let body = get_json(
+                self.http
+                    .get(url)
+                    .query(&[("q", query), ("format", "json")]),
+            )
+            .await?;
+            Ok(parse_results(
+                &body["results"],
+                "title",
+                "url",
+                "content",
+                limit,
+            ))
+        })
+    }
+}
+
+pub struct JsonApi {
+    http: reqwest::Client,
+    url: String,
+    apikey: Option<String>,
+    config: JsonSearchConfig,
+}
+
+impl SearchProvider for JsonApi {
+    fn search<'a>(
+        &'a self,
+        query: &'a str,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Vec<SearchResult>, String>> {
+        Box::pin(async move {
+            let config = &self.config;
+            let mut req = self
+                .http
+                .get(&self.url)
+                .query(&[(config.query_param.as_str(), query)])
+                .query(&config.params);
+            if let Some(apikey) = &self.apikey {
+                req = if config.apikey_header.eq_ignore_ascii_case("authorization") {
+                    req.bearer_auth(apikey)
+                } else {
+                    req.header(config.apikey_header.as_str(), apikey)
+                };
+            }
+
+            let body = get_json(req).await?;
+            let results = config
+                .results_path
+                .split('.')
+                .filter(|v| !v.is_empty())
+                .fold(&body, |v, key| &v[key]);
+            Ok(parse_results(
+                results,
+                &config.title_field,
+                &config.url_field,
+                &config.snippet_field,
+                limit,
+            ))
+        })
+    }
+}
+
+async fn get_json(req: reqwest::RequestBuilder) -> Result<Value, String> {
+    let res = req.send().await.map_err(|err| err.to_string())?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(format!("搜索接口返回 {}", status));
+    }
+    res.json().await.map_err(|err| err.to_string())
+}
+
+fn parse_results(
+    results: &Value,
+    title: &str,
+    url: &str,
+    snippet: &str,
+    limit: usize,
+) -> Vec<SearchResult> {
+    results
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|v| {
+            Some(SearchResult {
+                title: v[title].as_str().unwrap_or_default().trim().to_string(),
+                url: v[url].as_str()?.to_string(),
+                snippet: v[snippet].as_str().unwrap_or_default().trim().to_string(),
+            })
+        })
+        .take(limit)
+        .collect()
+}
+
+/// 让模型搜索网页的工具。同一次提问中的搜索结果连续编号，记录在 `citations` 中，
+/// 回答完成后用 [`append_footnotes`] 补上被引用结果的脚注
+pub struct SearchTool {
+    provider: Arc<dyn SearchProvider>,
+    max_results: usize,
+    snippet_max_chars: usize,
+    citations: Arc<Mutex<Vec<SearchResult>>>,
+}
+
+impl SearchTool {
+    pub fn new(
+        provider: Arc<dyn SearchProvider>,
+        config: &SearchConfig,
+        citations: Arc<Mutex<Vec<SearchResult>>>,
+    ) -> SearchTool {
+        SearchTool {
+            provider,
+            max_results: config.max_results,
+            snippet_max_chars: config.snippet_max_chars,
+            citations,
+        }
+    }
+}
+
+impl Tool for SearchTool {
+    fn name(&self) -> &str {
+        "web_search"
+    }
+
+    fn description(&self) -> &str {
+        "搜索网页，需要最新信息或不确定的事实时使用。结果带有编号 [n]，\
+回答中使用搜索结果时，在相应句子后用 Markdown 脚注 `[^n]` 标注来源，不需要自己写脚注定义。"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": {
+                    "type": "string",
+                    "description": "搜索关键词",
+                },
+            },
+            "required": ["query"],
+        })
+    }
+
+    fn execute(&self, args: Value) -> BoxFuture<'_, Result<String, String>> {
+        Box::pin(async move {
+            let query = args["query"].as_str().ok_or("缺少参数 query")?;
+            let results = self.provider.search(query, self.max_results).await?;
+            if results.is_empty() {
+                return Ok("没有搜索结果".to_string());
+            }
+
+            let mut citations = self.citations.lock();
+            let mut lines = Vec::with_capacity(results.len());
+            for result in results {
+                citations.push(result);
+                let result = citations.last().unwrap();
+                lines.push(format!(
+                    "[{}] {}\n{}\n{}",
+                    citations.len(),
+                    result.title,
+                    result.url,
+                    crate::truncate_chars(&result.snippet, self.snippet_max_chars)
+                ));
+            }
+            Ok(lines.join("\n\n"))
+        })
+    }
+}
+
+/// 为回答中引用的 `[^n]` 补上脚注定义，没有对应搜索结果的编号保持原样
+pub fn append_footnotes(content: &str, citations: &[SearchResult]) -> String {
+    let mut content = content.trim_end().to_string();
+    let mut defs = Vec::new();
+    for (i, citation) in citations.iter().enumerate() {
+        let label = format!("[^{}]", i + 1);
+        // 模型自己写了定义时不重复添加
+        if !content.contains(&label) || content.contains(&format!("{}:", label)) {
+            continue;
+        }
+        let title = match citation.title.is_empty() {
+            true => citation.url.as_str(),
+            false => citation.title.as_str(),
+        };
+        defs.push(format!(
+            "{}: [{}]({})",
+            label,
+            title.replace(['[', ']'], ""),
+            citation.url
+        ));
+    }
+    if !defs.is_empty() {
+        content.push_str("\n\n");
+        content.push_str(&defs.join("\n"));
+    }
+    content
+}
+
+#[test]
+fn test_append_footnotes() {
+    let citations = vec![
+        SearchResult {
+            title: "Rust [官网]".to_string(),
+            url: "https://www.rust-lang.org".to_string(),
+            snippet: String::new(),
+        },
+        SearchResult {
+            title: String::new(),
+            url: "https://doc.rust-lang.org".to_string(),
+            snippet: String::new(),
+        },
+        SearchResult {
+            title: "未引用".to_string(),
+            url: "https://example.com".to_string(),
+            snippet: String::new(),
+        },
+    ];
+
+    assert_eq!(
+        append_footnotes("Rust 很快[^1]，文档很全[^2]。\n", &citations),
+        "Rust 很快[^1]，文档很全[^2]。\n\n\
+[^1]: [Rust 官网](https://www.rust-lang.org)\n\
+[^2]: [https://doc.rust-lang.org](https://doc.rust-lang.org)"
+    );
+    assert_eq!(append_footnotes("没有引用", &citations), "没有引用");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_search_providers() {
+    use crate::mock::MockServer;
+
+    let server = MockServer::start(vec![
+        (
+            200,
+            json!({
+                "results": [
+                    { "title": "Rust", "url": "https://www.rust-lang.org", "content": "A language" },
+                    { "title": "no url" },
+                    { "title": "Docs", "url": "https://doc.rust-lang.org", "content": "Docs" }
+                ]
+            }),
+        ),
+        (
+            200,
+            json!({
+                "web": { "results": [
+                    { "title": "Brave", "url": "https://search.brave.com", "description": "Search" }
+                ] }
+            }),
+        ),
+    ])
+    .await;
+
+    let config = SearchConfig {
+        enabled: true,
+        url: server.base_url.clone(),
+        ..SearchConfig::default()
+    };
+    let citations = Arc::new(Mutex::new(Vec::new()));
+    let tool = SearchTool::new(new(&config).unwrap(), &config, citations.clone());
+    let res = tool.execute(json!({ "query": "rust 语言" })).await.unwrap();
+    assert_eq!(
+        res,
+        "[1] Rust\nhttps://www.rust-lang.org\nA language\n\n[2] Docs\nhttps://doc.rust-lang.org\nDocs"
+    );
+    assert_eq!(citations.lock().len(), 2);
+
+    let mut config = SearchConfig {
+        enabled: true,
+        provider: SearchProviderKind::Json,
+        url: format!("{}/web/search", server.base_url),
+        apikey: Some("key".to_string()),
+        ..SearchConfig::default()
+    };
+    config.json.apikey_header = "X-Subscription-Token".to_string();
+    config.json.results_path = "web.results".to_string();
+    config.json.snippet_field = "description".to_string();
+    let results = new(&config).unwrap().search("rust", 5).await.unwrap();
+    assert_eq!(results[0].snippet, "Search");
+
+    let heads = server.heads();
+    assert!(
+        heads[0]
+            .line
+            .starts_with("GET /v1/search?q=rust+%E8%AF%AD%E8%A8%80&format=json")
+    );
+    assert_eq!(heads[1].line, "GET /v1/web/search?q=rust");
+    assert_eq!(heads[1].header("x-subscription-token"), Some("key"));
+}
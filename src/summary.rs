@@ -0,0 +1,218 @@
+//! `%summary` 群聊总结命令
+
+use crate::config::OutputMode;
+use crate::req::{self, ChatClient, Completion, Message};
+use crate::tools::ToolRegistry;
+use crate::{Context, MsgEvent, history};
+use kovi::log;
+use std::time::{Duration, Instant};
+
+static SUMMARY_PROMPT: &str = r#"你是一个群聊记录总结助手。用户会给出一段群聊记录，每行一条消息，格式为 `[月-日 时:分] 昵称: 内容`。请使用中文和 Markdown 输出结构化的总结：
+1. 先用一两句话概括整体内容；
+2. 按话题分节，每个话题用小标题，列出主要观点、结论以及主要参与者；
+3. 如果有待办事项、约定或未解决的问题，单独列出。
+只根据聊天记录总结，不要编造内容。"#;
+
+static PARTIAL_PROMPT: &str = r#"你是一个群聊记录总结助手。用户会给出一段较长群聊记录中的一部分，每行一条消息，格式为 `[月-日 时:分] 昵称: 内容`。请按话题列出这部分记录的要点，保留时间范围、参与者和结论，后续会与其他部分的要点合并成完整总结。只根据聊天记录总结，不要编造内容。"#;
+
+static MERGE_PROMPT: &str = r#"你是一个群聊记录总结助手。用户会给出同一段群聊记录按时间顺序分段整理出的要点，请把它们合并，去掉重复内容，使用中文和 Markdown 输出结构化的总结：
+1. 先用一两句话概括整体内容；
+2. 按话题分节，每个话题用小标题，列出主要观点、结论以及主要参与者；
+3. 如果有待办事项、约定或未解决的问题，单独列出。
+只根据给出的要点总结，不要编造内容。"#;
+
+/// 解析 `summary [条数]`，不是总结命令时返回 None，未指定条数时返回 `Some(None)`
+pub fn parse_command(text: &str) -> Option<Option<usize>> {
+    let rest = text.trim().strip_prefix("summary")?;
+    if rest.is_empty() {
+        return Some(None);
+    }
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    rest.trim().parse().ok().map(Some)
+}
+
+/// 总结群聊记录，按提问的输出方式发送，返回是否成功
+pub async fn run(e: &MsgEvent, ctx: &Context, count: Option<usize>, mode: OutputMode) -> bool {
+    let config = &ctx.config.summary;
+
+    let Some(group_id) = crate::group_id(e) else {
+        e.reply_and_quote("群聊总结只能在群聊中使用");
+        return false;
+    };
+    if !ctx.config.history.allows(group_id) {
+        e.reply_and_quote("本群没有开启聊天记录查询");
+        return false;
+    }
+
+    let count = count
+        .unwrap_or(config.default_messages)
+        .clamp(1, config.max_messages);
+
+    let msgs = match history::fetch_group_history(&ctx.bot, group_id, count).await {
+        Ok(v) => history::apply_privacy(v, &ctx.config.history, ctx.config.history.max_minutes),
+        Err(err) => {
+            log::error!("aiqa: Failed to fetch group history: {}", err);
+            e.reply_and_quote(format!("获取聊天记录失败\n\n{}", err));
+            return false;
+        }
+    };
+    if msgs.is_empty() {
+        e.reply_and_quote("没有可以总结的聊天记录");
+        return false;
+    }
+
+    let lines = history::format_messages(&msgs);
+    let res = match summarize(&ctx.chat_client, &lines, config.chunk_chars).await {
+        Ok(v) => v,
+        Err(err) => {
+            e.reply_and_quote(format!("总结失败了Q-Q\n\n{}", err));
+            return false;
+        }
+    };
+
+    crate::send_answer(e, ctx, mode, None, &res, None)
+        .await
+        .is_some()
+}
+
+/// 总结聊天记录。超过 `chunk_chars` 时按行切分，分别提取要点后再合并（map-reduce）
+pub async fn summarize(
+    client: &ChatClient,
+    lines: &str,
+    chunk_chars: usize,
+) -> Result<Completion, String> {
+    let mut total = Completion {
+        content: String::new(),
+        model: String::new(),
+        usage: None,
+        latency: Duration::ZERO,
+        used_tools: false,
+        provider: String::new(),
+        reasoning: None,
+    };
+
+    // 所有分段共用一次总超时
+    let deadline = client.deadline();
+    let mut text = lines.to_string();
+    let mut prompt = SUMMARY_PROMPT;
+    let mut last_len = usize::MAX;
+    loop {
+        let chunks = chunk_lines(&text, chunk_chars);
+        // 要点合并后没有变短时不再继续切分，避免无限循环
+        if chunks.len() <= 1 || chunks.len() >= last_len {
+            let res = request(client, prompt, text, deadline).await?;
+            merge(&mut total, res);
+            return Ok(total);
+        }
+
+        last_len = chunks.len();
+        let mut parts = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let res = request(client, PARTIAL_PROMPT, chunk, deadline).await?;
+            parts.push(format!("## 第 {} 部分\n\n{}", i + 1, res.content));
+            merge(&mut total, res);
+        }
+        text = parts.join("\n\n");
+        prompt = MERGE_PROMPT;
+    }
+}
+
+async fn request(
+    client: &ChatClient,
+    system: &str,
+    text: String,
+    deadline: Instant,
+) -> Result<Completion, String> {
+    client
+        .request_with_system(
+            system,
+            vec![Message::new_with_user(text)],
+            &ToolRegistry::new(),
+            deadline,
+        )
+        .await
+        .map_err(|err| err.to_string())
+}
+
+fn merge(total: &mut Completion, res: Completion) {
+    req::add_usage(&mut total.usage, res.usage);
+    total.latency += res.latency;
+    total.model = res.model;
+    total.provider = res.provider;
+    total.content = res.content;
+}
+
+/// 按行把文本切分为不超过 `max_chars` 个字符的块，单行过长时单独成块
+pub fn chunk_lines(text: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_chars = 0;
+
+    for line in text.lines() {
+        let chars = line.chars().count() + 1;
+        if current_chars > 0 && current_chars + chars > max_chars {
+            chunks.push(std::mem::take(&mut current));
+            current_chars = 0;
+        }
+        current.push_str(line);
+        current.push('\n');
+        current_chars += chars;
+    }
+    if current_chars > 0 {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+#[test]
+fn test_parse_command() {
+    assert_eq!(parse_command("summary"), Some(None));
+    assert_eq!(parse_command("summary 200"), Some(Some(200)));
+    assert_eq!(parse_command(" summary  50 "), Some(Some(50)));
+    assert_eq!(parse_command("summary of rust"), None);
+    assert_eq!(parse_command("summaryxx"), None);
+    assert_eq!(parse_command("什么是summary"), None);
+}
+
+#[test]
+fn test_chunk_lines() {
+    let text = "aaaa\nbbbb\ncccc\ndddddddddd\ne";
+    assert_eq!(
+        chunk_lines(text, 10),
+        vec!["aaaa\nbbbb\n", "cccc\n", "dddddddddd\n", "e\n"]
+    );
+    assert_eq!(chunk_lines(text, 100).len(), 1);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_summarize_map_reduce() {
+    use crate::mock::{self, MockServer};
+
+    let server = MockServer::start(vec![
+        (200, mock::answer("part 1")),
+        (200, mock::answer("part 2")),
+        (200, mock::answer("summary")),
+    ])
+    .await;
+    let client = mock::client(&server.base_url);
+
+    let res = summarize(
+        &client,
+        "[01-01 10:00] a: hello\n[01-01 10:01] b: world",
+        40,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(res.content, "summary");
+    assert_eq!(res.usage.unwrap().total_tokens, 45);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    let merged = requests[2]["messages"][1]["content"].as_str().unwrap();
+    assert!(merged.contains("part 1") && merged.contains("part 2"));
+}
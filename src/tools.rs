@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use kovi::futures_util::future::BoxFuture;
+use kovi::serde_json::Value;
+
+use crate::req::ToolCall;
+
+/// 可以被模型调用的工具
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// 参数的 JSON Schema
+    fn parameters(&self) -> Value;
+
+    /// 执行工具，返回给模型的结果。出错时返回的信息同样会交给模型
+    fn execute(&self, args: Value) -> BoxFuture<'_, Result<String, String>>;
+}
+
+#[derive(Default, Clone)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> ToolRegistry {
+        ToolRegistry::default()
+    }
+
+    pub fn register<T: Tool + 'static>(&mut self, tool: T) {
+        self.tools.retain(|v| v.name() != tool.name());
+        self.tools.push(Arc::new(tool));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Tool> {
+        self.tools.iter().map(|tool| tool.as_ref())
+    }
+
+    /// 执行一次工具调用，任何错误都转换为文本交给模型处理
+    pub async fn call(&self, call: &ToolCall) -> String {
+        let Some(tool) = self.tools.iter().find(|tool| tool.name() == call.name) else {
+            return format!("error: unknown tool `{}`", call.name);
+        };
+
+        let args = if call.arguments.trim().is_empty() {
+            Value::Object(Default::default())
+        } else {
+            match kovi::serde_json::from_str(&call.arguments) {
+                Ok(v) => v,
+                Err(err) => return format!("error: invalid arguments: {}", err),
+            }
+        };
+
+        match tool.execute(args).await {
+            Ok(v) => v,
+            Err(err) => format!("error: {}", err),
+        }
+    }
+}